@@ -14,7 +14,7 @@ fn main() {
     println!("---------------|---------|---------|---------|---------|---------|---------|---------");
 
 
-    let benches: [(_, &dyn Fn(usize) -> (Duration, Diff, Diff)); 6] = [
+    let benches: [(_, &Bench); 6] = [
         ("baseline",        &|i| bench_func(baseline, i)),
         ("vec",             &|i| bench_func(fill_vec, i)),
         ("btree",           &|i| bench_func(fill_btree, i)),
@@ -43,6 +43,10 @@ fn main() {
 }
 
 
+/// Function running a single benchmark for a given number of items
+type Bench = dyn Fn(usize) -> (Duration, Diff, Diff);
+
+
 fn baseline(num: usize) -> impl IntoIterator<Item=u64> {
     let mut curr = Default::default();
     random_items(num).map(move |v| {
@@ -88,21 +92,15 @@ fn fill_sortbuf_jumbo(num: usize) -> impl IntoIterator<Item=u64> {
 
 
 fn fill_sortbuf_threads(num: usize) -> impl IntoIterator<Item=u64> {
-    use std::sync::{Arc, Mutex};
-
-    let buf: Arc<Mutex<sortbuf::SortBuf<std::cmp::Reverse<u64>>>> = Default::default();
-
-    random_items(NUM_THREADS).map(|seed| {
-        let mut inserter = sortbuf::Inserter::new(buf.clone());
-        std::thread::spawn(move || inserter.insert_items_reversed(
-            random_items_with_seed(num / NUM_THREADS, seed.into())
-        ).expect("Error while inserting"))
-    }).collect::<Vec<_>>().into_iter().try_for_each(|h| h.join()).expect("Error while waiting for threads");
-
-    Arc::try_unwrap(buf)
-        .expect("Failed to unwrap buffer!")
-        .into_inner()
-        .expect("Failed to unlock buffer!")
+    let seeds: Vec<_> = random_items(NUM_THREADS).collect();
+
+    sortbuf::SortBuf::new()
+        .fill_parallel(
+            NUM_THREADS.try_into().expect("Invalid number of threads"),
+            |inserter, n| inserter.insert_items_reversed(random_items_with_seed(num / NUM_THREADS, seeds[n].into())),
+        )
+        .map_err(|(e, _)| e)
+        .expect("Error while inserting")
         .unreversed()
 }
 
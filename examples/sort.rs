@@ -7,42 +7,39 @@
 
 
 fn main() {
-    use std::io::BufRead;
-    use std::sync::{Arc, Mutex};
+    use std::io::Write;
+    use std::sync::Mutex;
 
     // We need to pre-collect the arguments (minus the progname) since
     // ArgsOs is both `!Send` and `!Sync`.
     let args: Vec<_> = std::env::args_os().skip(1).collect();
+    let threads = std::thread::available_parallelism().unwrap_or(std::num::NonZeroUsize::MIN);
 
-    let lines: Arc<Mutex<sortbuf::SortBuf<_>>> = Default::default();
+    // Lines are stored as byte records, without an allocation per line
+    let lines: Mutex<sortbuf::RecordBuf> = Default::default();
 
-    if args.len() > 0 {
-        let paths: Arc<Mutex<_>> = Mutex::new(args.into_iter()).into();
+    if !args.is_empty() {
+        let paths = Mutex::new(args.into_iter());
 
         // We delegate the actual work to multiple worker threads
-        let workers: Vec<_> = (0..std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2))
-            .map(|_| {
-                let paths = paths.clone();
-                let lines = lines.clone();
-                std::thread::spawn(move || {
-                    let mut inserter = sortbuf::Inserter::new(lines);
-                    while let Some(path) = paths.lock().unwrap().next() {
-                        let lines = std::io::BufReader::new(std::fs::File::open(path).unwrap())
-                            .lines()
-                            .map(|l| l.unwrap());
-                        inserter.insert_items_reversed(lines).unwrap()
-                    }
-                })
-            }).collect();
-
-        // Wait for workers to finish...
-        workers.into_iter().try_for_each(|t| t.join()).unwrap()
+        std::thread::scope(|s| (0..threads.get()).for_each(|_| {
+            s.spawn(|| {
+                let mut inserter = sortbuf::RecordInserter::new(&lines);
+                while let Some(path) = paths.lock().unwrap().next() {
+                    let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+                    inserter.insert_lines(file).unwrap()
+                }
+            });
+        }))
     } else {
-        sortbuf::Inserter::new(lines.clone())
-            .insert_items_reversed(std::io::stdin().lock().lines().map(|l| l.unwrap()))
-            .unwrap()
+        sortbuf::RecordInserter::new(&lines).insert_lines(std::io::stdin().lock()).unwrap()
     }
 
-    lines.lock().unwrap().take().unreversed().for_each(|l| println!("{}", l));
+    let lines = lines.into_inner().unwrap();
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+    lines.iter().for_each(|l| {
+        stdout.write_all(l).unwrap();
+        stdout.write_all(b"\n").unwrap();
+    });
+    stdout.flush().unwrap()
 }
-
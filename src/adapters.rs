@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: MIT
+//! Adapters wrapping [BucketAccumulator]s
+//!
+//! This module provides wrappers adding cross-cutting behavior to arbitrary
+//! [BucketAccumulator]s, such as counting or transforming the [Bucket]s added.
+//! All wrappers forward all functions of the [BucketAccumulator] trait to the
+//! wrapped accumulator.
+
+use core::num::NonZeroUsize;
+
+use super::{Bucket, BucketAccumulator};
+use super::error::{InsertionError, InsertionResult};
+
+
+/// [BucketAccumulator] counting the [Bucket]s and items added
+///
+/// Only [Bucket]s confirmed by the wrapped accumulator are counted. If adding
+/// a [Bucket] fails, the items of the [Bucket] handed back are discounted. If
+/// the wrapped accumulator transforms [Bucket]s, this is the transformed one.
+///
+/// # Examples
+///
+/// ```
+/// let mut sortbuf = sortbuf::SortBuf::new();
+/// let mut counting = sortbuf::adapters::Counting::new(&mut sortbuf);
+/// sortbuf::Inserter::new(&mut counting).insert_items(0..1000).expect("Failed to insert items");
+/// assert_eq!(counting.buckets(), 1);
+/// assert_eq!(counting.items(), 1000);
+/// ```
+#[derive(Debug, Default)]
+pub struct Counting<A> {
+    inner: A,
+    buckets: usize,
+    items: usize,
+}
+
+impl<A> Counting<A> {
+    /// Wrap the given accumulator
+    pub fn new(inner: A) -> Self {
+        Self{inner, buckets: 0, items: 0}
+    }
+
+    /// Retrieve the number of [Bucket]s added successfully
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Retrieve the number of items in all [Bucket]s added successfully
+    pub fn items(&self) -> usize {
+        self.items
+    }
+
+    /// Retrieve a reference to the wrapped accumulator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Retrieve a mutable reference to the wrapped accumulator
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Retrieve the wrapped accumulator
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A: BucketAccumulator> BucketAccumulator for Counting<A> {
+    type Item = A::Item;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        let len = bucket.len();
+        self.inner.add_bucket(bucket)?;
+        self.buckets += 1;
+        self.items += len;
+        Ok(())
+    }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        // The wrapped accumulator need not fail on the bucket it consumed
+        // last, e.g. if it buffers buckets. Hence, we count all buckets
+        // consumed and discount the one handed back.
+        let (mut num, mut items) = (0usize, 0usize);
+        let res = self.inner.add_buckets(buckets.into_iter().inspect(|b| {
+            num += 1;
+            items += b.len();
+        }));
+        if let Err((_, bucket)) = &res {
+            num = num.saturating_sub(1);
+            items = items.saturating_sub(bucket.len());
+        }
+        self.buckets += num;
+        self.items += items;
+        res
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.inner.reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.inner.preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.inner.on_inserter_finished()
+    }
+}
+
+
+/// [BucketAccumulator] transforming [Bucket]s before adding them
+///
+/// This wrapper passes each [Bucket] through a function before adding the
+/// result to the wrapped accumulator. Empty [Bucket]s resulting from the
+/// transformation are discarded. If adding a [Bucket] fails, the transformed
+/// [Bucket] is returned.
+///
+/// # Examples
+///
+/// ```
+/// use sortbuf::Bucket;
+///
+/// let mut sortbuf = sortbuf::SortBuf::new();
+/// let dedup = sortbuf::adapters::MapBuckets::new(&mut sortbuf, |b: Bucket<_>| {
+///     let mut items = b.into_vec();
+///     items.dedup();
+///     Bucket::from_sorted(items).unwrap_or_else(Bucket::from_unsorted)
+/// });
+/// sortbuf::Inserter::new(dedup).insert_items([3, 1, 3, 2, 1]).expect("Failed to insert items");
+/// assert!(sortbuf.into_iter().eq([3, 2, 1]));
+/// ```
+#[derive(Debug, Default)]
+pub struct MapBuckets<A, F> {
+    inner: A,
+    func: F,
+}
+
+impl<A, F> MapBuckets<A, F> {
+    /// Wrap the given accumulator, transforming [Bucket]s via `func`
+    pub fn new(inner: A, func: F) -> Self {
+        Self{inner, func}
+    }
+
+    /// Retrieve a reference to the wrapped accumulator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Retrieve a mutable reference to the wrapped accumulator
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Retrieve the wrapped accumulator
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A, F> BucketAccumulator for MapBuckets<A, F>
+where A: BucketAccumulator,
+      F: FnMut(Bucket<A::Item>) -> Bucket<A::Item>,
+{
+    type Item = A::Item;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        let bucket = (self.func)(bucket);
+        if bucket.is_empty() {
+            return Ok(())
+        }
+        self.inner.add_bucket(bucket)
+    }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        let buckets = buckets.into_iter().map(&mut self.func).filter(|b| !b.is_empty());
+        self.inner.add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.inner.reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.inner.preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.inner.on_inserter_finished()
+    }
+}
+
+
+/// [BucketAccumulator] passing a reference to each [Bucket] to a function
+///
+/// This wrapper calls a function with each [Bucket] before adding it to the
+/// wrapped accumulator, e.g. for logging or for copying items elsewhere.
+///
+/// # Examples
+///
+/// ```
+/// let mut sortbuf = sortbuf::SortBuf::new();
+/// let mut max = None;
+/// let inspect = sortbuf::adapters::Inspect::new(&mut sortbuf, |b: &sortbuf::Bucket<_>| {
+///     max = max.max(b.max().copied())
+/// });
+/// sortbuf::Inserter::new(inspect).insert_items([3, 1, 2]).expect("Failed to insert items");
+/// assert_eq!(max, Some(3));
+/// ```
+#[derive(Debug, Default)]
+pub struct Inspect<A, F> {
+    inner: A,
+    func: F,
+}
+
+impl<A, F> Inspect<A, F> {
+    /// Wrap the given accumulator, passing [Bucket]s to `func`
+    pub fn new(inner: A, func: F) -> Self {
+        Self{inner, func}
+    }
+
+    /// Retrieve a reference to the wrapped accumulator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Retrieve a mutable reference to the wrapped accumulator
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Retrieve the wrapped accumulator
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A, F> BucketAccumulator for Inspect<A, F>
+where A: BucketAccumulator,
+      F: FnMut(&Bucket<A::Item>),
+{
+    type Item = A::Item;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        (self.func)(&bucket);
+        self.inner.add_bucket(bucket)
+    }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.inner.add_buckets(buckets.into_iter().inspect(&mut self.func))
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.inner.reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.inner.preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.inner.on_inserter_finished()
+    }
+}
+
+
+/// [BucketAccumulator] failing after a given number of [Bucket]s
+///
+/// This wrapper forwards a given number of [Bucket]s to the wrapped accumulator.
+/// Once that number is exhausted, any attempt to add a [Bucket] fails with an
+/// [InsertionError], until more [Bucket]s are allowed via
+/// [set_remaining](Self::set_remaining). Reservations via
+/// [BucketAccumulator::reserve_buckets] fail as well in that state. This allows
+/// testing the recovery from insertion errors.
+///
+/// # Examples
+///
+/// ```
+/// # use std::num::NonZeroUsize;
+/// let mut sortbuf = sortbuf::SortBuf::new();
+/// let failing = sortbuf::adapters::FailAfter::new(&mut sortbuf, 1);
+/// let mut inserter = sortbuf::Inserter::new(failing);
+/// inserter.set_bucket_size(NonZeroUsize::new(10).unwrap());
+///
+/// let mut items = 0..100;
+/// assert!(inserter.insert_items(items.by_ref()).is_err());
+///
+/// inserter.bucket_accumulator_mut().set_remaining(usize::MAX);
+/// inserter.insert_items(items).expect("Failed to insert items");
+/// drop(inserter);
+/// assert!(sortbuf.into_iter().eq((0..100).rev()));
+/// ```
+#[derive(Debug, Default)]
+pub struct FailAfter<A> {
+    inner: A,
+    remaining: usize,
+}
+
+impl<A> FailAfter<A> {
+    /// Wrap the given accumulator, allowing `buckets` [Bucket]s to be added
+    pub fn new(inner: A, buckets: usize) -> Self {
+        Self{inner, remaining: buckets}
+    }
+
+    /// Set the number of [Bucket]s which may still be added
+    pub fn set_remaining(&mut self, buckets: usize) -> &mut Self {
+        self.remaining = buckets;
+        self
+    }
+
+    /// Retrieve the number of [Bucket]s which may still be added
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Retrieve a reference to the wrapped accumulator
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Retrieve a mutable reference to the wrapped accumulator
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Retrieve the wrapped accumulator
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+impl<A: BucketAccumulator> BucketAccumulator for FailAfter<A> {
+    type Item = A::Item;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.inner.add_bucket(bucket)?;
+                self.remaining = remaining;
+                Ok(())
+            },
+            None => Err((InsertionError::injected(), bucket)),
+        }
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        if self.remaining == 0 {
+            return Err(InsertionError::injected())
+        }
+        self.inner.reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.inner.preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.inner.on_inserter_finished()
+    }
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: MIT
+//! Types and utilities for controlling the allocation of bucket storage
+
+use alloc::vec::Vec;
+#[cfg(target_has_atomic = "ptr")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::error::InsertionError;
+
+
+/// Source of storage for [Bucket](super::Bucket)s
+///
+/// Implementations of this trait provide the [Vec]s in which [Inserter]s
+/// accumulate items and get notified when a [SortBuf] or an
+/// [Iter](super::SortBuf::into_iter) grows, sheds or releases the storage of a
+/// bucket. This allows tracking, limiting or recycling the memory used for
+/// buckets.
+///
+/// Since instances are shared between [Inserter]s, buffers and iterators, all
+/// functions take `&self`. Implementations are provided for references as well
+/// as, if supported by the target, [Arc](alloc::sync::Arc)s to allocators.
+///
+/// # Other notes
+///
+/// Buckets are plain [Vec]s backed by the global allocator. Hence, this trait
+/// is not a means for supplying storage from other sources, e.g. arenas or huge
+/// pages, but a hook for observing and recycling bucket storage. All storage of
+/// buckets passing from an [Inserter] via a [BucketAccumulator] to a [SortBuf]
+/// or an iterator is allocated, grown, shrunk and released through the
+/// allocator of the respective component, including storage
+/// [adopted](Self::adopt) from or [disowned](Self::disown) to the user along
+/// the way. Thus, all components handling the same buckets should use the same
+/// allocator. This includes storage allocated for items split off buckets,
+/// e.g. by [SortBuf::drain_from] or `Iter::next_batch`, and for merging buckets
+/// via [SortBuf::into_sorted_vec].
+///
+/// The following components always use the global allocator directly:
+///
+/// * the heaps of [RunInserter](super::RunInserter)s,
+/// * [SortMap](super::SortMap)s, which also combine values via the global
+///   allocator, and
+/// * the batches in which an iterator collects items added via `Iter::push`,
+///   until they are turned into buckets.
+///
+/// [BucketAccumulator]: super::BucketAccumulator
+/// [Inserter]: super::Inserter
+/// [SortBuf]: super::SortBuf
+/// [SortBuf::drain_from]: super::SortBuf::drain_from
+/// [SortBuf::into_sorted_vec]: super::SortBuf::into_sorted_vec
+pub trait BucketAllocator<T> {
+    /// Allocate storage for at least `capacity` items
+    ///
+    /// This function returns an empty [Vec] with a capacity of at least
+    /// `capacity` items. If the allocation failed or was refused, an error is
+    /// returned.
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError>;
+
+    /// Grow the storage of `items` to hold at least `additional` more items
+    ///
+    /// If the storage could not be grown, an error is returned and `items` is
+    /// left unaltered.
+    ///
+    /// The default implementation calls [Vec::try_reserve_exact].
+    fn grow(&self, items: &mut Vec<T>, additional: usize) -> Result<(), InsertionError> {
+        items.try_reserve_exact(additional).map_err(Into::into)
+    }
+
+    /// Shrink the storage of `items` to a capacity of at least `min_capacity`
+    ///
+    /// The default implementation calls [Vec::shrink_to].
+    fn shrink(&self, items: &mut Vec<T>, min_capacity: usize) {
+        items.shrink_to(min_capacity)
+    }
+
+    /// Release the memory backing unused item slots of `items` in place
+    ///
+    /// This function is called by iterators for releasing the memory of the
+    /// item slots from `items.len()` up to `resident`, which don't hold items.
+    /// It returns the number of leading item slots which may still be backed by
+    /// memory.
+    ///
+    /// On Linux, the default implementation releases all memory pages lying
+    /// entirely within those slots via `madvise(MADV_DONTNEED)`. This neither
+    /// moves items nor alters the capacity of `items`. On other targets, it
+    /// [shrinks](Self::shrink) `items` to its length, which may involve a
+    /// reallocation, and returns the resulting capacity.
+    fn release_unused(&self, items: &mut Vec<T>, resident: usize) -> usize {
+        #[cfg(target_os = "linux")]
+        {
+            let len = items.len();
+            super::bucket::release_pages(items, len, resident);
+            len
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = resident;
+            self.shrink(items, 0);
+            items.capacity()
+        }
+    }
+
+    /// Release the storage of `items`
+    ///
+    /// The default implementation simply drops the [Vec].
+    fn release(&self, items: Vec<T>) {
+        drop(items)
+    }
+
+    /// Take over the storage of `items` allocated elsewhere
+    ///
+    /// This function is called when storage not allocated through this
+    /// allocator becomes a bucket, e.g. via
+    /// [Inserter::insert_vec](super::Inserter::insert_vec). It will eventually
+    /// be shrunk or released through this allocator. If the storage can't be
+    /// taken over, an error is returned.
+    ///
+    /// The default implementation does nothing.
+    fn adopt(&self, items: &Vec<T>) -> Result<(), InsertionError> {
+        let _ = items;
+        Ok(())
+    }
+
+    /// Hand out the storage of `items` to the user
+    ///
+    /// This function is called when the storage of a bucket leaves the bucket
+    /// without being released, e.g. via `Iter::next_batch`.
+    ///
+    /// The default implementation does nothing.
+    fn disown(&self, items: &Vec<T>) {
+        let _ = items;
+    }
+}
+
+impl<T, M: BucketAllocator<T> + ?Sized> BucketAllocator<T> for &M {
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError> {
+        (*self).allocate(capacity)
+    }
+
+    fn grow(&self, items: &mut Vec<T>, additional: usize) -> Result<(), InsertionError> {
+        (*self).grow(items, additional)
+    }
+
+    fn shrink(&self, items: &mut Vec<T>, min_capacity: usize) {
+        (*self).shrink(items, min_capacity)
+    }
+
+    fn release_unused(&self, items: &mut Vec<T>, resident: usize) -> usize {
+        (*self).release_unused(items, resident)
+    }
+
+    fn release(&self, items: Vec<T>) {
+        (*self).release(items)
+    }
+
+    fn adopt(&self, items: &Vec<T>) -> Result<(), InsertionError> {
+        (*self).adopt(items)
+    }
+
+    fn disown(&self, items: &Vec<T>) {
+        (*self).disown(items)
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T, M: BucketAllocator<T> + ?Sized> BucketAllocator<T> for alloc::sync::Arc<M> {
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError> {
+        self.as_ref().allocate(capacity)
+    }
+
+    fn grow(&self, items: &mut Vec<T>, additional: usize) -> Result<(), InsertionError> {
+        self.as_ref().grow(items, additional)
+    }
+
+    fn shrink(&self, items: &mut Vec<T>, min_capacity: usize) {
+        self.as_ref().shrink(items, min_capacity)
+    }
+
+    fn release_unused(&self, items: &mut Vec<T>, resident: usize) -> usize {
+        self.as_ref().release_unused(items, resident)
+    }
+
+    fn release(&self, items: Vec<T>) {
+        self.as_ref().release(items)
+    }
+
+    fn adopt(&self, items: &Vec<T>) -> Result<(), InsertionError> {
+        self.as_ref().adopt(items)
+    }
+
+    fn disown(&self, items: &Vec<T>) {
+        self.as_ref().disown(items)
+    }
+}
+
+
+/// [BucketAllocator] using the global allocator
+///
+/// This is the default [BucketAllocator] used by [Inserter](super::Inserter)s
+/// and iterators.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Global;
+
+impl<T> BucketAllocator<T> for Global {
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError> {
+        let mut items = Vec::new();
+        items.try_reserve_exact(capacity)?;
+        Ok(items)
+    }
+}
+
+
+/// [BucketAllocator] enforcing a limit on the memory used for buckets
+///
+/// This allocator uses the global allocator but refuses allocations which would
+/// exceed a given number of bytes in total. Memory is accounted for based on
+/// the capacity of the [Vec]s allocated, grown, shrunk, released, adopted and
+/// disowned through the allocator. Thus, the limit is only enforced faithfully
+/// if the [Inserter](super::Inserter)s, the buffer and the final iterator all
+/// use the same `MemoryLimit`. Components bypassing [BucketAllocator]s, which
+/// are listed in its documentation, are not accounted for. Storage committed
+/// via [Inserter::insert_vec](super::Inserter::insert_vec) counts against the
+/// limit until it is released or handed out again via `Iter::next_batch`.
+/// Growing storage involves moving the items to a new allocation, which keeps
+/// the accounting exact.
+///
+/// Note that on Linux, iterators release the memory of consumed items in place
+/// rather than shrinking buckets. In that case, the memory is only returned to
+/// the budget once a bucket is exhausted.
+///
+/// # Examples
+///
+/// ```
+/// let limit = sortbuf::MemoryLimit::new(1024);
+/// let mut sortbuf = sortbuf::SortBuf::with_allocator(&limit);
+/// let mut inserter = sortbuf::Inserter::with_allocator(&mut sortbuf, &limit);
+/// inserter.set_bucket_bytesize(512);
+/// inserter.insert_items(0..64u64).expect("Failed to insert items");
+/// assert!(inserter.insert_items(64..128).is_err());
+/// drop(inserter);
+///
+/// assert!(sortbuf.into_iter().eq((0..128).rev()));
+/// assert_eq!(limit.available(), 1024);
+/// ```
+#[cfg(target_has_atomic = "ptr")]
+#[derive(Debug)]
+pub struct MemoryLimit {
+    limit: usize,
+    available: AtomicUsize,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl MemoryLimit {
+    /// Create a new allocator limiting the memory used to `limit` bytes
+    pub fn new(limit: usize) -> Self {
+        Self{limit, available: AtomicUsize::new(limit)}
+    }
+
+    /// Retrieve the limit, in bytes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Retrieve the number of bytes which may still be allocated
+    pub fn available(&self) -> usize {
+        self.available.load(Ordering::Acquire)
+    }
+
+    /// Claim `bytes` bytes from the remaining budget
+    fn claim(&self, bytes: usize) -> Result<(), InsertionError> {
+        self.available
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |a| a.checked_sub(bytes))
+            .map(drop)
+            .map_err(|_| InsertionError::limit_exceeded())
+    }
+
+    /// Return `bytes` bytes to the remaining budget
+    ///
+    /// Returning more than was claimed indicates an accounting error.
+    fn unclaim(&self, bytes: usize) {
+        let before = self.available.fetch_add(bytes, Ordering::AcqRel);
+        debug_assert!(
+            before.checked_add(bytes).is_some_and(|a| a <= self.limit),
+            "Released more memory than claimed",
+        );
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> BucketAllocator<T> for MemoryLimit {
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError> {
+        let bytes = capacity.saturating_mul(core::mem::size_of::<T>());
+        self.claim(bytes)?;
+        let items = Global.allocate(capacity).inspect_err(|_| self.unclaim(bytes))?;
+
+        // The global allocator may hand out more than we asked for.
+        let actual = bytesize(&items);
+        if actual > bytes {
+            if let Err(e) = self.claim(actual - bytes) {
+                self.unclaim(bytes);
+                return Err(e)
+            }
+        }
+        Ok(items)
+    }
+
+    fn grow(&self, items: &mut Vec<T>, additional: usize) -> Result<(), InsertionError> {
+        if items.capacity() - items.len() >= additional {
+            return Ok(())
+        }
+
+        // Moving the items to a new allocation keeps the accounting exact.
+        let mut grown = self.allocate(items.len().saturating_add(additional))?;
+        grown.append(items);
+        self.release(core::mem::replace(items, grown));
+        Ok(())
+    }
+
+    fn shrink(&self, items: &mut Vec<T>, min_capacity: usize) {
+        let before = bytesize(items);
+        items.shrink_to(min_capacity);
+        self.unclaim(before.saturating_sub(bytesize(items)))
+    }
+
+    fn release(&self, items: Vec<T>) {
+        self.unclaim(bytesize(&items))
+    }
+
+    fn adopt(&self, items: &Vec<T>) -> Result<(), InsertionError> {
+        self.claim(bytesize(items))
+    }
+
+    fn disown(&self, items: &Vec<T>) {
+        self.unclaim(bytesize(items))
+    }
+}
+
+
+/// [BucketAllocator] recycling the storage of released buckets
+///
+/// This allocator retains the storage of buckets released through it, e.g. by
+/// a buffer or iterator using it, and hands it out again for new buckets, e.g.
+/// to [Inserter](super::Inserter)s. Thus,
+/// repeated cycles of filling and draining buffers reuse memory rather than
+/// allocating and deallocating it over and over. Allocations which can't be
+/// served from the pool are delegated to the [Global] allocator.
+///
+/// The pool retains at most a given number of allocations. When serving an
+/// allocation, the smallest allocation in the pool which is sufficiently large
+/// is used. Note that in that case, buckets may end up larger than requested.
+/// Thus, the pool is most effective if all buckets are of the same size.
+///
+/// # Examples
+///
+/// ```
+/// let pool = sortbuf::BucketPool::new(8);
+/// for _ in 0..3 {
+///     let mut sortbuf = sortbuf::SortBuf::with_allocator(&pool);
+///     let mut inserter = sortbuf::Inserter::with_allocator(&mut sortbuf, &pool);
+///     inserter.set_bucket_size(std::num::NonZeroUsize::new(128).unwrap());
+///     inserter.insert_items(0..1024u32).expect("Failed to insert items");
+///     drop(inserter);
+///
+///     assert!(sortbuf.into_iter().eq((0..1024).rev()));
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct BucketPool<T> {
+    pool: std::sync::Mutex<Vec<Vec<T>>>,
+    max_buckets: usize,
+}
+
+#[cfg(feature = "std")]
+impl<T> BucketPool<T> {
+    /// Create a new pool retaining at most `max_buckets` allocations
+    pub fn new(max_buckets: usize) -> Self {
+        Self{pool: Default::default(), max_buckets}
+    }
+
+    /// Retrieve the number of allocations currently retained
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Check whether no allocations are currently retained
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Release all allocations currently retained
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+
+    /// Lock the pool
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Vec<T>>> {
+        self.pool.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> BucketAllocator<T> for BucketPool<T> {
+    fn allocate(&self, capacity: usize) -> Result<Vec<T>, InsertionError> {
+        let mut pool = self.lock();
+        let best = pool
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.capacity() >= capacity)
+            .min_by_key(|(_, v)| v.capacity())
+            .map(|(n, _)| n);
+        match best {
+            Some(n) => Ok(pool.swap_remove(n)),
+            None => {
+                drop(pool);
+                Global.allocate(capacity)
+            },
+        }
+    }
+
+    fn release(&self, mut items: Vec<T>) {
+        items.clear();
+        if items.capacity() == 0 || self.max_buckets == 0 {
+            return
+        }
+
+        let mut pool = self.lock();
+        if pool.len() < self.max_buckets {
+            if pool.try_reserve(1).is_ok() {
+                pool.push(items)
+            }
+        } else if let Some(smallest) = pool.iter_mut().min_by_key(|v| v.capacity()) {
+            if smallest.capacity() < items.capacity() {
+                *smallest = items
+            }
+        }
+    }
+}
+
+
+/// Determine the size of the storage allocated for a [Vec], in bytes
+#[cfg(target_has_atomic = "ptr")]
+fn bytesize<T>(items: &Vec<T>) -> usize {
+    items.capacity().saturating_mul(core::mem::size_of::<T>())
+}
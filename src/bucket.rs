@@ -1,8 +1,12 @@
 // SPDX-License-Identifier: MIT
 //! Types representing individual buckets and related utilities
 
-use std::cmp::Ordering;
-use std::fmt;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+use super::BucketAllocator;
+use super::error::InsertionError;
 
 
 /// Default size for [Bucket]s
@@ -20,7 +24,23 @@ pub const DEFAULT_BUCKET_BYTESIZE: usize = 16*1024*1024;
 
 /// A collection of items to be committed to a [SortBuf](super::SortBuf)
 ///
-/// Users of the library will usually not use this type directly.
+/// A bucket holds items sorted in ascending order. Users of the library will
+/// usually not use this type directly. However, custom [BucketAccumulator]s may
+/// use the functions provided for constructing, inspecting and deconstructing
+/// buckets.
+///
+/// # Examples
+///
+/// ```
+/// let mut bucket = sortbuf::Bucket::from_unsorted(vec![3, 1, 4, 1, 5]);
+/// assert_eq!(bucket.as_slice(), [1, 1, 3, 4, 5]);
+///
+/// let upper = bucket.split_off(&3);
+/// assert_eq!(bucket.max(), Some(&1));
+/// assert_eq!(upper.into_vec(), [3, 4, 5]);
+/// ```
+///
+/// [BucketAccumulator]: super::BucketAccumulator
 ///
 /// # Other notes
 ///
@@ -29,27 +49,89 @@ pub const DEFAULT_BUCKET_BYTESIZE: usize = 16*1024*1024;
 pub struct Bucket<T>(Vec<T>);
 
 impl<T: Ord> Bucket<T> {
-    /// Create a bucket from a [Vec] of items
+    /// Create a bucket from a [Vec] of items in arbitrary order
+    ///
+    /// The items are sorted in place. This function will never reallocate,
+    /// i.e. the bucket retains any overcapacity of the given [Vec].
     ///
     /// # Time complexity
     ///
-    /// Construction of a sorted bucket involves sorting the items. Thus, it
-    /// comes with a run-time cost of O(_b_*log(_b_)) with bucket size _b_.
-    pub(crate) fn new(mut items: Vec<T>) -> Self {
-        items.shrink_to_fit();
-        items.sort_unstable();
+    /// This function comes with a run-time cost of O(_b_*log(_b_)) with bucket
+    /// size _b_. However, items already sorted in ascending or descending order
+    /// are detected, in which case the cost is only O(_b_).
+    pub fn from_unsorted(mut items: Vec<T>) -> Self {
+        sort(&mut items);
+        Self(items)
+    }
+
+    /// Create a bucket from a [Vec] of items sorted in ascending order
+    ///
+    /// The order of the items is verified in a single pass. If the items are
+    /// not sorted in ascending order, they are returned as an error.
+    pub fn from_sorted(items: Vec<T>) -> Result<Self, Vec<T>> {
+        if items.windows(2).all(|p| p[0] <= p[1]) {
+            Ok(Self(items))
+        } else {
+            Err(items)
+        }
+    }
+
+    /// Create a bucket from a [Vec] of items already sorted in ascending order
+    ///
+    /// The caller is responsible for the items being sorted.
+    pub(crate) fn from_sorted_unchecked(items: Vec<T>) -> Self {
         Self(items)
     }
 
-    /// Convert this bucket back to a [Vec]
-    pub(crate) fn into_inner(self) -> Vec<T> {
+    /// Retrieve the items in this bucket in ascending order
+    pub fn as_slice(&self) -> &[T] {
+        &self.0
+    }
+
+    /// Retrieve the [Vec] holding the items in this bucket
+    pub(crate) fn as_vec(&self) -> &Vec<T> {
+        &self.0
+    }
+
+    /// Convert this bucket into a [Vec] of items in ascending order
+    pub fn into_vec(self) -> Vec<T> {
         self.0
     }
 
+    /// Retrieve the least item in this bucket
+    pub fn min(&self) -> Option<&T> {
+        self.0.first()
+    }
+
+    /// Retrieve the greatest item in this bucket
+    pub fn max(&self) -> Option<&T> {
+        self.0.last()
+    }
+
+    /// Split this bucket at the given `key`
+    ///
+    /// This function moves all items greater than or equal to `key` into a new
+    /// bucket, which is returned. This bucket retains all items less than
+    /// `key`.
+    ///
+    /// # Time complexity
+    ///
+    /// Locating the split point comes with a run-time cost of O(log(_b_)) with
+    /// bucket size _b_. Moving out _r_ items costs O(_r_).
+    pub fn split_off(&mut self, key: &T) -> Self {
+        let pos = self.0.partition_point(|i| i < key);
+        Self(self.0.split_off(pos))
+    }
+
     /// Retrieve the number of items in this bucket
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    /// Check whether this bucket is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl<T: Ord> fmt::Debug for Bucket<T> {
@@ -73,36 +155,168 @@ impl<T: Ord> fmt::Debug for Bucket<T> {
 ///
 /// The omission of an implementation of [Clone] for this type is on purpose, as
 /// it holds non-shared ownership over significant amounts of data.
-pub(crate) struct SortedBucket<T: Ord>(Vec<T>);
+///
+/// Besides the [Vec], this type tracks the number of item slots for which the
+/// memory was not yet released, i.e. the slots in front of the first slot
+/// released via [shink_to_fit](Self::shink_to_fit).
+pub(crate) struct SortedBucket<T: Ord>(Vec<T>, usize);
 
 impl<T: Ord> SortedBucket<T> {
     /// Retrieve the current overcapacity of this bucket
     ///
     /// The overcapacity is defined as the number of additional items the inner
-    /// [Vec] has capacity for.
+    /// [Vec] has capacity for, excluding slots for which the memory was already
+    /// released.
     #[inline(always)]
     pub fn overcapacity(&self) -> usize {
-        self.0.capacity() - self.0.len()
+        self.1 - self.0.len()
     }
 
-    /// Shrink the inner [Vec] to the number of items it currently holds
+    /// Release the memory of item slots not holding an item
     ///
-    /// This operation sheds overcapacity.
+    /// The memory is released via [BucketAllocator::release_unused] of the
+    /// given `allocator`. By default, this releases the memory pages backing
+    /// unused item slots in place on Linux and shrinks the inner [Vec] on other
+    /// targets.
     #[inline(always)]
-    pub fn shink_to_fit(&mut self) {
-        self.0.shrink_to_fit()
+    pub fn shink_to_fit(&mut self, allocator: &impl BucketAllocator<T>) {
+        self.1 = allocator.release_unused(&mut self.0, self.1);
+    }
+
+    /// Retrieve the greatest item in this bucket
+    #[inline(always)]
+    pub fn peek(&self) -> Option<&T> {
+        self.0.last()
+    }
+
+    /// Remove and return all items not less than `bound`
+    ///
+    /// This function removes all items which are greater than or equal to the
+    /// given `bound` from this bucket and returns them in descending order. If
+    /// no `bound` is given, all items are removed. If all items are removed,
+    /// the storage of this bucket is reused. Otherwise, new storage is
+    /// allocated for the items removed via the given `allocator`. If that
+    /// allocation fails, an error is returned and the bucket is left unaltered.
+    ///
+    /// # Time complexity
+    ///
+    /// Locating the items to remove comes with a run-time cost of O(log(_b_))
+    /// with bucket size _b_. Moving out _r_ items costs O(_r_).
+    pub fn split_off_from(
+        &mut self,
+        bound: Option<&T>,
+        allocator: &impl BucketAllocator<T>,
+    ) -> Result<Vec<T>, InsertionError> {
+        let pos = bound.map(|b| self.0.partition_point(|i| i < b)).unwrap_or(0);
+        if pos == 0 {
+            let mut items = core::mem::take(self).into_inner();
+            items.reverse();
+            return Ok(items)
+        }
+
+        let mut items = allocator.allocate(self.0.len() - pos)?;
+        items.extend(self.0.drain(pos..).rev());
+        Ok(items)
+    }
+
+    /// Split off the upper part of this bucket
+    ///
+    /// This function moves all items for which `keep` returns `false` into a
+    /// new bucket, which is returned. `keep` must return `true` for a (possibly
+    /// empty) prefix of the items and `false` for the rest. If no item is kept,
+    /// the storage of this bucket is moved to the new bucket. Otherwise, new
+    /// storage is allocated for the items moved out via the given `allocator`.
+    /// If that allocation fails, an error is returned and the bucket is left
+    /// unaltered.
+    ///
+    /// This bucket retains its capacity. Thus, the items split off may be put
+    /// back via [rejoin](Self::rejoin) without any allocation.
+    ///
+    /// # Time complexity
+    ///
+    /// Locating the split point comes with a run-time cost of O(log(_b_)) with
+    /// bucket size _b_. Moving out _r_ items costs O(_r_).
+    pub fn split_upper(
+        &mut self,
+        keep: impl FnMut(&T) -> bool,
+        allocator: &impl BucketAllocator<T>,
+    ) -> Result<Self, InsertionError> {
+        let pos = self.0.partition_point(keep);
+        if pos == 0 {
+            return Ok(core::mem::take(self))
+        }
+
+        let mut upper = allocator.allocate(self.0.len() - pos)?;
+        upper.extend(self.0.drain(pos..));
+        let capacity = upper.capacity();
+        Ok(Self(upper, capacity))
+    }
+
+    /// Put back the upper part split off via [split_upper](Self::split_upper)
+    ///
+    /// The bucket must not have been altered since `upper` was split off.
+    pub fn rejoin(&mut self, mut upper: Self) {
+        if self.0.is_empty() {
+            *self = upper
+        } else {
+            debug_assert!(upper.0.len() <= self.0.capacity() - self.0.len(), "Rejoining would allocate");
+            self.0.append(&mut upper.0)
+        }
+    }
+
+    /// Merge another bucket into this one
+    ///
+    /// This function merges the items of both buckets into the allocation of
+    /// the bucket with the greater capacity, which is grown via the given
+    /// `allocator` if necessary. The storage of the other bucket is released
+    /// through the `allocator`. If growing fails, an error is returned
+    /// alongside both unaltered buckets.
+    ///
+    /// # Time complexity
+    ///
+    /// Merging two buckets comes with a run-time cost of O(_b_) with _b_
+    /// denoting the combined number of items.
+    pub fn merge(
+        mut self,
+        mut other: Self,
+        allocator: &impl BucketAllocator<T>,
+    ) -> Result<Self, (InsertionError, Self, Self)> {
+        if self.0.capacity() < other.0.capacity() {
+            core::mem::swap(&mut self, &mut other);
+        }
+
+        if let Err(e) = allocator.grow(&mut self.0, other.0.len()) {
+            return Err((e, self, other))
+        }
+
+        merge_into(&mut self.0, &mut other.0);
+        allocator.release(other.0);
+        self.1 = self.0.capacity();
+        Ok(self)
+    }
+
+    /// Convert this bucket into a [Vec] of items in ascending order
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T: Ord> Default for SortedBucket<T> {
+    fn default() -> Self {
+        Self(Vec::new(), 0)
     }
 }
 
 impl<T: Ord> From<Bucket<T>> for SortedBucket<T> {
     fn from(Bucket(items): Bucket<T>) -> Self {
-        Self(items)
+        let capacity = items.capacity();
+        Self(items, capacity)
     }
 }
 
 impl<T: Ord> ExactSizeIterator for SortedBucket<T> {}
 
-impl<T: Ord> std::iter::FusedIterator for SortedBucket<T> {}
+impl<T: Ord> core::iter::FusedIterator for SortedBucket<T> {}
 
 impl<T: Ord> Iterator for SortedBucket<T> {
     type Item = T;
@@ -129,7 +343,7 @@ impl<T: Ord> Ord for SortedBucket<T> {
 impl<T: Ord> PartialOrd for SortedBucket<T> {
     #[inline(always)]
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        PartialOrd::partial_cmp(&self.0.last(), &other.0.last())
+        Some(self.cmp(other))
     }
 }
 
@@ -148,3 +362,122 @@ impl<T: Ord> fmt::Debug for SortedBucket<T> {
     }
 }
 
+
+/// Sort items in ascending order
+///
+/// Items already sorted in ascending or descending order are detected, in which
+/// case they are not sorted but at most reversed. The detection bails out at
+/// the first item out of order in either direction.
+fn sort<T: Ord>(items: &mut [T]) {
+    let mut pairs = items.windows(2);
+    match pairs.find(|p| p[0] != p[1]).map(|p| p[0] < p[1]) {
+        Some(true) if pairs.all(|p| p[0] <= p[1])   => (),
+        Some(false) if pairs.all(|p| p[0] >= p[1])  => items.reverse(),
+        Some(_)                                     => items.sort_unstable(),
+        None                                        => (),
+    }
+}
+
+
+/// Release the memory backing the item slots `items[from..to]`
+///
+/// This function releases all memory pages lying entirely within the given
+/// range of item slots via `madvise(MADV_DONTNEED)`. The item slots must not
+/// hold any items, i.e. `from` must not be less than the length of `items`.
+/// Subsequent accesses to the released memory will yield zeroed pages.
+#[cfg(target_os = "linux")]
+pub(crate) fn release_pages<T>(items: &mut Vec<T>, from: usize, to: usize) {
+    debug_assert!(from >= items.len() && to <= items.capacity(), "Slots not released");
+
+    let size = core::mem::size_of::<T>();
+    // SAFETY: `sysconf` has no preconditions.
+    let page = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        p if p > 0  => p as usize,
+        _           => return,
+    };
+    if size == 0 || from >= to {
+        return
+    }
+
+    let base = items.as_mut_ptr() as usize;
+    let start = (base + from * size).next_multiple_of(page);
+    let end = (base + to * size) / page * page;
+    if start < end {
+        // SAFETY: the pages lie entirely within the allocation of `items` but
+        //         outside of the region holding items. Releasing them is thus
+        //         equivalent to writing zeroes to spare capacity. The call can
+        //         only fail for invalid arguments, in which case nothing
+        //         happens.
+        unsafe {
+            libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_DONTNEED);
+        }
+    }
+}
+
+
+/// Move all items from one sorted [Vec] into another, preserving the order
+///
+/// Both `dst` and `src` must be sorted in ascending order and `dst` must have
+/// sufficient spare capacity for holding all items in `src`. After the
+/// operation, `dst` will hold all items in ascending order and `src` will be
+/// empty.
+///
+/// If a comparison panics, all items will end up in `dst` in an unspecified
+/// order.
+fn merge_into<T: Ord>(dst: &mut Vec<T>, src: &mut Vec<T>) {
+    assert!(dst.capacity() - dst.len() >= src.len(), "Insufficient capacity for merge");
+
+    /// Guard for the merge state
+    ///
+    /// The items `dst[..i]` and `src[..j]` are yet to be merged. The items
+    /// already merged occupy `dst[i+j..len]`, leaving a gap of `j` items. On
+    /// drop, the remaining items of `src` are moved into that gap.
+    struct Merge<'a, T> {
+        dst: &'a mut Vec<T>,
+        src: &'a mut Vec<T>,
+        i: usize,
+        j: usize,
+        len: usize,
+    }
+
+    impl<T> Drop for Merge<'_, T> {
+        fn drop(&mut self) {
+            // SAFETY: the gap `dst[i..i+j]` is exactly large enough for holding
+            //         the remaining `src[..j]`, which we move out of `src`.
+            unsafe {
+                let gap = self.dst.as_mut_ptr().add(self.i);
+                core::ptr::copy_nonoverlapping(self.src.as_ptr(), gap, self.j);
+                self.dst.set_len(self.len);
+            }
+        }
+    }
+
+    let len = dst.len() + src.len();
+    let (i, j) = (dst.len(), src.len());
+    // SAFETY: from here on, the guard is responsible for all items.
+    unsafe {
+        dst.set_len(0);
+        src.set_len(0);
+    }
+
+    let mut merge = Merge{dst, src, i, j, len};
+    while merge.i > 0 && merge.j > 0 {
+        let dst = merge.dst.as_mut_ptr();
+        // SAFETY: `dst[i-1]` and `src[j-1]` are valid, unmerged items while the
+        //         target `dst[i+j-1]` lies in the gap (or is `dst[i-1]` only if
+        //         `j` is zero, which is excluded).
+        unsafe {
+            let a = dst.add(merge.i - 1);
+            let b = merge.src.as_ptr().add(merge.j - 1);
+            let target = dst.add(merge.i + merge.j - 1);
+            if *b >= *a {
+                core::ptr::copy_nonoverlapping(b, target, 1);
+                merge.j -= 1;
+            } else {
+                core::ptr::copy_nonoverlapping(a, target, 1);
+                merge.i -= 1;
+            }
+        }
+    }
+}
+
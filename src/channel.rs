@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MIT
+//! Utilities for passing buckets through channels
+
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+
+use super::{Bucket, BucketAccumulator, Inserter};
+use super::error::{InsertionError, InsertionResult};
+
+
+impl<T: Ord> BucketAccumulator for Sender<Bucket<T>> {
+    type Item = T;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        self.send(bucket).map_err(|mpsc::SendError(b)| (InsertionError::disconnected(), b))
+    }
+}
+
+impl<T: Ord> BucketAccumulator for SyncSender<Bucket<T>> {
+    type Item = T;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        self.send(bucket).map_err(|mpsc::SendError(b)| (InsertionError::disconnected(), b))
+    }
+}
+
+
+/// Add all [Bucket]s received through a channel to an accumulator
+///
+/// This function receives [Bucket]s from the given `receiver` and adds them to
+/// the given `accumulator` until all senders are disconnected. If adding a
+/// [Bucket] fails, no further [Bucket]s are received and an error is returned
+/// alongside the [Bucket] in question. [Bucket]s still queued in the channel
+/// are dropped alongside the `receiver`, while subsequent sends fail and hand
+/// back their [Bucket] to the sender.
+pub fn collect_buckets<A: BucketAccumulator>(
+    receiver: Receiver<Bucket<A::Item>>,
+    accumulator: &mut A,
+) -> InsertionResult<Bucket<A::Item>> {
+    receiver.into_iter().try_for_each(|b| accumulator.add_bucket(b))
+}
+
+
+/// Collector thread adding [Bucket]s received through a channel to an accumulator
+///
+/// A `Collector` owns a [BucketAccumulator] on a dedicated thread. Other
+/// threads pass [Bucket]s to that thread through a bounded channel, e.g. via
+/// [Inserter]s created via [inserter](Self::inserter). Thus, all mutation of
+/// the accumulator happens on a single thread, without any locking. The bound
+/// of the channel provides backpressure: if the collector thread falls behind,
+/// [Inserter]s will block when committing [Bucket]s.
+///
+/// # Examples
+///
+/// ```
+/// let collector = sortbuf::Collector::spawn(sortbuf::SortBuf::new(), 4);
+/// std::thread::scope(|s| (0..4).for_each(|n| {
+///     let mut inserter = collector.inserter();
+///     s.spawn(move || inserter
+///         .insert_items((0..1000).map(|i| 4*i+n))
+///         .expect("Failed to insert items"));
+/// }));
+///
+/// let sortbuf = collector.finish().expect("Failed to collect buckets");
+/// assert!(sortbuf.into_iter().eq((0..4000).rev()));
+/// ```
+#[derive(Debug)]
+pub struct Collector<A: BucketAccumulator> {
+    sender: SyncSender<Bucket<A::Item>>,
+    handle: thread::JoinHandle<Collected<A>>,
+}
+
+impl<A> Collector<A>
+where A: BucketAccumulator + Send + 'static,
+      A::Item: Send + 'static,
+{
+    /// Spawn a new collector thread for the given `accumulator`
+    ///
+    /// The channel through which [Bucket]s are passed holds at most `bound`
+    /// [Bucket]s. A `bound` of zero results in each commit blocking until the
+    /// collector thread received the [Bucket].
+    pub fn spawn(mut accumulator: A, bound: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(bound);
+        let handle = thread::spawn(move || match collect_buckets(receiver, &mut accumulator) {
+            Ok(()) => Ok(accumulator),
+            Err((e, bucket)) => Err((e, accumulator, bucket)),
+        });
+        Self{sender, handle}
+    }
+}
+
+impl<A: BucketAccumulator> Collector<A> {
+    /// Retrieve a sender for passing [Bucket]s to the collector thread
+    pub fn sender(&self) -> SyncSender<Bucket<A::Item>> {
+        self.sender.clone()
+    }
+
+    /// Create an [Inserter] committing [Bucket]s to the collector thread
+    pub fn inserter(&self) -> Inserter<SyncSender<Bucket<A::Item>>> {
+        Inserter::new(self.sender())
+    }
+
+    /// Wait for the collector thread to finish and retrieve the accumulator
+    ///
+    /// The collector thread finishes once all senders, including those held
+    /// by [Inserter]s, are dropped. If adding a [Bucket] to the accumulator
+    /// failed, the collector thread stops and an error is returned alongside
+    /// the accumulator and the [Bucket] which could not be added. [Inserter]s
+    /// will subsequently fail with an error as well, retaining their items.
+    /// See [collect_buckets] regarding [Bucket]s queued at that point.
+    ///
+    /// # Panics
+    ///
+    /// If the collector thread panicked, the panic is propagated.
+    pub fn finish(self) -> Collected<A> {
+        drop(self.sender);
+        self.handle.join().unwrap_or_else(|p| std::panic::resume_unwind(p))
+    }
+}
+
+
+/// Result of a [Collector] thread
+///
+/// On failure, the accumulator is returned alongside the error and the
+/// [Bucket] which could not be added.
+pub type Collected<A> = Result<A, (InsertionError, A, Bucket<<A as BucketAccumulator>::Item>)>;
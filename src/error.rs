@@ -1,32 +1,119 @@
 // SPDX-License-Identifier: MIT
 //! Types and utilities related to error handling and reporting
 
-use std::collections::TryReserveError;
-use std::error::Error;
-use std::fmt;
+use alloc::collections::TryReserveError;
+use core::error::Error;
+use core::fmt;
 
 
 /// Insertion error
 ///
 /// This type conveys errors occuring during the insertion of items to a buffer.
 #[derive(Debug)]
-pub struct InsertionError(TryReserveError);
+pub struct InsertionError(Kind);
+
+impl InsertionError {
+    /// Create an error signalling that a memory limit would be exceeded
+    ///
+    /// [BucketAllocator](super::BucketAllocator)s enforcing a limit on the
+    /// memory allocated through them should use this error for refusing an
+    /// allocation.
+    pub fn limit_exceeded() -> Self {
+        Self(Kind::LimitExceeded)
+    }
+
+    /// Create an error signalling that the receiving end of a channel is gone
+    ///
+    /// [BucketAccumulator](super::BucketAccumulator)s forwarding buckets to
+    /// some other entity should use this error if that entity no longer accepts
+    /// buckets.
+    pub fn disconnected() -> Self {
+        Self(Kind::Disconnected)
+    }
+
+    /// Create an error injected for testing purposes
+    pub(crate) fn injected() -> Self {
+        Self(Kind::Injected)
+    }
+}
 
 impl From<TryReserveError> for InsertionError {
     fn from(inner: TryReserveError) -> Self {
-        Self(inner)
+        Self(Kind::Alloc(inner))
     }
 }
 
 impl Error for InsertionError {
     fn cause(&self) -> Option<&(dyn Error + 'static)> {
-        Some(&self.0)
+        match &self.0 {
+            Kind::Alloc(inner)      => Some(inner),
+            Kind::LimitExceeded     => None,
+            Kind::Disconnected      => None,
+            Kind::Injected          => None,
+        }
     }
 }
 
 impl fmt::Display for InsertionError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.write_str("Could not add items to accumulator")
+        match self.0 {
+            Kind::Alloc(_)          => fmt.write_str("Could not add items to accumulator"),
+            Kind::LimitExceeded     => fmt.write_str("Memory limit exceeded"),
+            Kind::Disconnected      => fmt.write_str("Receiving end disconnected"),
+            Kind::Injected          => fmt.write_str("Injected failure"),
+        }
+    }
+}
+
+
+/// Kinds of [InsertionError]s
+#[derive(Debug)]
+enum Kind {
+    /// An allocation failed
+    Alloc(TryReserveError),
+    /// An allocation was refused as it would exceed a memory limit
+    LimitExceeded,
+    /// The receiving end of a channel is disconnected
+    Disconnected,
+    /// A failure was injected deliberately
+    Injected,
+}
+
+
+/// Error for insertions of items from a fallible source
+///
+/// This type conveys either an [InsertionError] or an error yielded by the
+/// source of the items to insert, e.g. by
+/// [Inserter::try_insert_items](super::Inserter::try_insert_items).
+#[derive(Debug)]
+pub enum TryInsertError<E> {
+    /// Items could not be inserted
+    Insertion(InsertionError),
+    /// The source of items yielded an error
+    Source(E),
+}
+
+impl<E> From<InsertionError> for TryInsertError<E> {
+    fn from(inner: InsertionError) -> Self {
+        Self::Insertion(inner)
+    }
+}
+
+impl<E: Error + 'static> Error for TryInsertError<E> {
+    fn cause(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Insertion(inner)  => Some(inner),
+            Self::Source(inner)     => Some(inner),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for TryInsertError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Insertion(inner)  => fmt::Display::fmt(inner, fmt),
+            Self::Source(inner)     => write!(fmt, "Could not retrieve item: {inner}"),
+        }
     }
 }
 
@@ -36,4 +123,3 @@ impl fmt::Display for InsertionError {
 /// In the case of an insertion failure, we need to return an `InsertionError`.
 /// However, we also want to return the item(s) we failed to insert in some way.
 pub type InsertionResult<T> = Result<(), (InsertionError, T)>;
-
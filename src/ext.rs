@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MIT
+//! Extension trait for sorting arbitrary [Iterator]s
+
+use core::cmp::Reverse;
+use core::iter::FusedIterator;
+
+use super::{iter, BucketAllocator, Global, KeyValue, SortBuf};
+use super::error::InsertionError;
+use super::inserter::Inserter;
+
+
+/// Extension trait for sorting the items of an [Iterator] via a [SortBuf]
+///
+/// This trait provides shorthands for the common pattern of creating a
+/// [SortBuf], inserting all items of an [Iterator] via an [Inserter] and
+/// iterating over the items of the buffer in order. It is implemented for all
+/// [Iterator]s.
+///
+/// Note that the sorting performed is not stable, i.e. the relative order of
+/// equal items is not preserved.
+///
+/// # Examples
+///
+/// ```
+/// use sortbuf::SortBufIteratorExt;
+///
+/// assert!([10, 20, 5, 17].into_iter().sortbuf_sorted().eq([5, 10, 17, 20]));
+/// assert!([10, 20, 5, 17].into_iter().sortbuf_sorted_desc().eq([20, 17, 10, 5]));
+/// assert!(["ccc", "a", "bb"].into_iter().sortbuf_sorted_by_key(|s| s.len()).eq(["a", "bb", "ccc"]));
+/// ```
+pub trait SortBufIteratorExt: Iterator + Sized {
+    /// Sort the items of this [Iterator] in ascending order
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails. Use
+    /// [try_sortbuf_sorted](Self::try_sortbuf_sorted) for handling allocation
+    /// failures.
+    fn sortbuf_sorted(self) -> Sorted<Self::Item> where Self::Item: Ord {
+        self.try_sortbuf_sorted().expect("Failed to insert items")
+    }
+
+    /// Sort the items of this [Iterator] in ascending order
+    ///
+    /// If an allocation fails during insertion, an error is returned.
+    fn try_sortbuf_sorted(self) -> Result<Sorted<Self::Item>, InsertionError> where Self::Item: Ord {
+        let mut buf = SortBuf::new();
+        Inserter::new(&mut buf).insert_items(self.map(Reverse))?;
+        Ok(Sorted(buf.into_iter()))
+    }
+
+    /// Sort the items of this [Iterator] in descending order
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails.
+    fn sortbuf_sorted_desc(self) -> iter::Iter<Self::Item> where Self::Item: Ord {
+        self.collect::<SortBuf<_>>().into_iter()
+    }
+
+    /// Sort the items of this [Iterator] in ascending order of a key
+    ///
+    /// The key of each item is extracted via `f` exactly once.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails.
+    fn sortbuf_sorted_by_key<K, F>(self, mut f: F) -> SortedByKey<K, Self::Item>
+    where K: Ord,
+          F: FnMut(&Self::Item) -> K,
+    {
+        let buf: SortBuf<_> = self.map(|value| Reverse(KeyValue::new(f(&value), value))).collect();
+        SortedByKey(buf.into_iter())
+    }
+}
+
+impl<I: Iterator> SortBufIteratorExt for I {}
+
+
+/// [Iterator] yielding items in ascending order
+///
+/// Instances of this type are created via
+/// [SortBufIteratorExt::sortbuf_sorted] and
+/// [SortBuf::drain_below](super::SortBuf::drain_below).
+#[derive(Debug)]
+pub struct Sorted<T: Ord, M: BucketAllocator<Reverse<T>> = Global>(pub(crate) iter::Iter<Reverse<T>, M>);
+
+impl<T: Ord, M: BucketAllocator<Reverse<T>>> ExactSizeIterator for Sorted<T, M> {}
+
+impl<T: Ord, M: BucketAllocator<Reverse<T>>> FusedIterator for Sorted<T, M> {}
+
+impl<T: Ord, M: BucketAllocator<Reverse<T>>> Iterator for Sorted<T, M> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|Reverse(item)| item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+
+/// [Iterator] yielding items in ascending order of their keys
+///
+/// Instances of this type are created via
+/// [SortBufIteratorExt::sortbuf_sorted_by_key].
+#[derive(Debug)]
+pub struct SortedByKey<K: Ord, V>(iter::Iter<Reverse<KeyValue<K, V>>>);
+
+impl<K: Ord, V> ExactSizeIterator for SortedByKey<K, V> {}
+
+impl<K: Ord, V> FusedIterator for SortedByKey<K, V> {}
+
+impl<K: Ord, V> Iterator for SortedByKey<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|Reverse(pair)| pair.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
@@ -1,12 +1,16 @@
 // SPDX-License-Identifier: MIT
 //! Types and utilites for adding items to a [SortBuf](super::SortBuf)
 
-use std::num::NonZeroUsize;
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+#[cfg(feature = "std")]
 use std::sync::{Arc, Mutex, RwLock};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
 
-use super::SortBuf;
+use super::{BucketAllocator, Global, HeapSize, RunInserter, SortBuf};
 use super::bucket::{self, Bucket};
-use super::error::{InsertionError, InsertionResult};
+use super::error::{InsertionError, InsertionResult, TryInsertError};
 
 
 /// Accumulator for [Bucket]s
@@ -28,6 +32,58 @@ pub trait BucketAccumulator {
     /// alongside the bucket which could not be added.
     fn add_bucket(&mut self, buckets: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>>;
 
+    /// Add multiple [Bucket]s to this accumulator
+    ///
+    /// This function adds all [Bucket]s yielded by `buckets` to the
+    /// accumulator, allowing implementations to amortize per-call costs such as
+    /// acquiring a lock. If adding a [Bucket] failed due to an (re-)allocation
+    /// failure, an error is returned alongside that bucket. Buckets not yet
+    /// consumed remain in the [Iterator] passed, which callers may retain by
+    /// passing a mutable reference, e.g. the result of [Iterator::by_ref].
+    ///
+    /// The default implementation calls [add_bucket](Self::add_bucket) for
+    /// each [Bucket].
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> where Self: Sized {
+        buckets.into_iter().try_for_each(|b| self.add_bucket(b))
+    }
+
+    /// Reserve capacity for at least `additional` more [Bucket]s
+    ///
+    /// [Inserter]s call this function with the number of [Bucket]s they expect
+    /// to commit, based on the size hint of the items passed to them. If the
+    /// reservation failed, an error is returned. [Inserter]s treat the
+    /// reservation as a hint and ignore such errors, as adding the [Bucket]s
+    /// individually may still succeed.
+    ///
+    /// The default implementation does nothing.
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        let _ = additional;
+        Ok(())
+    }
+
+    /// Retrieve the bucket size this accumulator prefers, in items
+    ///
+    /// New [Inserter]s use this size as their initial target bucket size
+    /// instead of one derived from the
+    /// [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
+    ///
+    /// The default implementation returns `None`, i.e. no preference.
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        None
+    }
+
+    /// Notify this accumulator that an [Inserter] finished
+    ///
+    /// [Inserter]s call this function when they are dropped, after committing
+    /// their final [Bucket]. Implementations may use this, e.g., for flushing
+    /// buffered buckets.
+    ///
+    /// The default implementation does nothing.
+    fn on_inserter_finished(&mut self) {}
+
     /// Create an [Inserter] for this accumulator
     ///
     /// Create a new [Inserter] for this accumulator. [Bucket]s committed though
@@ -36,6 +92,15 @@ pub trait BucketAccumulator {
     fn inserter(self) -> Inserter<Self> where Self: Sized {
         Inserter::new(self)
     }
+
+    /// Create a [RunInserter] for this accumulator
+    ///
+    /// Create a new [RunInserter] for this accumulator. The [RunInserter]
+    /// returned generates runs via replacement selection using a heap of a
+    /// size near a [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
+    fn run_inserter(self) -> RunInserter<Self> where Self: Sized {
+        RunInserter::new(self)
+    }
 }
 
 impl<A: BucketAccumulator> BucketAccumulator for &mut A {
@@ -44,49 +109,192 @@ impl<A: BucketAccumulator> BucketAccumulator for &mut A {
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
         (*self).add_bucket(bucket)
     }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        (*self).add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        (*self).reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        (**self).preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        (*self).on_inserter_finished()
+    }
 }
 
-impl<T: Ord> BucketAccumulator for SortBuf<T> {
+impl<T: Ord, M: BucketAllocator<T>> BucketAccumulator for SortBuf<T, M> {
     type Item = T;
 
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
         match self.buckets.try_reserve(1) {
-            Ok(_)   => Ok(self.buckets.push(bucket.into())),
+            Ok(_)   => {
+                self.buckets.push(bucket.into());
+                Ok(())
+            },
             Err(e)  => Err((e.into(), bucket)),
         }
     }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        let mut buckets = buckets.into_iter();
+        let _ = self.buckets.try_reserve(buckets.size_hint().0);
+        buckets.try_for_each(|b| self.add_bucket(b))
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.buckets.try_reserve(additional).map_err(Into::into)
+    }
 }
 
+#[cfg(feature = "std")]
 impl<A: BucketAccumulator> BucketAccumulator for Mutex<A> {
     type Item = A::Item;
 
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        self.get_mut().expect("Could not lock mutex!").add_bucket(bucket)
+    }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.get_mut().expect("Could not lock mutex!").add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.get_mut().expect("Could not lock mutex!").reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.lock().expect("Could not lock mutex!").preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.get_mut().expect("Could not lock mutex!").on_inserter_finished()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: BucketAccumulator> BucketAccumulator for &Mutex<A> {
+    type Item = A::Item;
+
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
         self.lock().expect("Could not lock mutex!").add_bucket(bucket)
     }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.lock().expect("Could not lock mutex!").add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.lock().expect("Could not lock mutex!").reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.lock().expect("Could not lock mutex!").preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.lock().expect("Could not lock mutex!").on_inserter_finished()
+    }
 }
 
+#[cfg(feature = "std")]
 impl<A: BucketAccumulator> BucketAccumulator for Arc<Mutex<A>> {
     type Item = A::Item;
 
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
         self.lock().expect("Could not lock mutex!").add_bucket(bucket)
     }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.lock().expect("Could not lock mutex!").add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.lock().expect("Could not lock mutex!").reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.lock().expect("Could not lock mutex!").preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.lock().expect("Could not lock mutex!").on_inserter_finished()
+    }
 }
 
+#[cfg(feature = "std")]
 impl<A: BucketAccumulator> BucketAccumulator for RwLock<A> {
     type Item = A::Item;
 
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
-        self.write().expect("Could not lock mutex!").add_bucket(bucket)
+        self.get_mut().expect("Could not lock mutex!").add_bucket(bucket)
+    }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.get_mut().expect("Could not lock mutex!").add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.get_mut().expect("Could not lock mutex!").reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.read().expect("Could not lock mutex!").preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.get_mut().expect("Could not lock mutex!").on_inserter_finished()
     }
 }
 
+#[cfg(feature = "std")]
 impl<A: BucketAccumulator> BucketAccumulator for Arc<RwLock<A>> {
     type Item = A::Item;
 
     fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
         self.write().expect("Could not lock mutex!").add_bucket(bucket)
     }
+
+    fn add_buckets(
+        &mut self,
+        buckets: impl IntoIterator<Item = Bucket<Self::Item>>,
+    ) -> InsertionResult<Bucket<Self::Item>> {
+        self.write().expect("Could not lock mutex!").add_buckets(buckets)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.write().expect("Could not lock mutex!").reserve_buckets(additional)
+    }
+
+    fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+        self.read().expect("Could not lock mutex!").preferred_bucket_size()
+    }
+
+    fn on_inserter_finished(&mut self) {
+        self.write().expect("Could not lock mutex!").on_inserter_finished()
+    }
 }
 
 
@@ -119,22 +327,109 @@ impl<A: BucketAccumulator> BucketAccumulator for Arc<RwLock<A>> {
 /// the availible memory and the number of `Inserter`s involved in the target
 /// use-case.
 ///
+/// Note that target sizes in bytes only account for the inline size of items,
+/// i.e. [core::mem::size_of]. For items owning memory on the heap, such as
+/// [String](alloc::string::String)s, buckets may thus use considerably more
+/// memory. With [heap accounting](Self::set_heap_accounting) enabled, such
+/// memory is accounted for via the [HeapSize] trait.
+///
+/// # Allocation
+///
+/// The storage for [Bucket]s is allocated through a [BucketAllocator]. By
+/// default, the [Global] allocator is used. A different allocator may be
+/// supplied via [with_allocator](Self::with_allocator).
+///
+/// By default, an allocation failure is reported to the caller which may then
+/// choose a smaller target bucket size. Alternatively, an `Inserter` may retry
+/// with progressively smaller buckets on its own, down to a floor set via
+/// [set_min_bucket_size](Self::set_min_bucket_size). Whether this happened can
+/// be queried via [degraded](Self::degraded).
+///
+/// # Interaction with the accumulator
+///
+/// A new `Inserter` uses the target bucket size
+/// [preferred](BucketAccumulator::preferred_bucket_size) by its
+/// [BucketAccumulator], if any. By default, full [Bucket]s are committed as
+/// soon as they are filled. Alternatively, an `Inserter` may hold back a
+/// number of full [Bucket]s set via
+/// [set_max_pending_buckets](Self::set_max_pending_buckets) and commit them in
+/// batches via [BucketAccumulator::add_buckets], at the latest when
+/// [insert_items](Self::insert_items) returns. Once dropped, an `Inserter`
+/// commits its remaining items and notifies its [BucketAccumulator] via
+/// [BucketAccumulator::on_inserter_finished].
+///
+/// # Flushing
+///
+/// Items accumulated in a partially filled bucket are not visible in the
+/// [BucketAccumulator] until that bucket is committed. For long-lived
+/// `Inserter`s, the partial bucket can be committed explicitly via
+/// [flush](Self::flush). In addition, an `Inserter` may flush automatically
+/// once the partial bucket holds a [maximum number](Self::set_max_items) of
+/// items or, with the `std` feature, its oldest item exceeds a maximum age set
+/// via `set_max_age`. These conditions are checked when items
+/// are inserted and via [flush_if_due](Self::flush_if_due).
 #[derive(Debug)]
-pub struct Inserter<A: BucketAccumulator> {
+pub struct Inserter<A: BucketAccumulator, M: BucketAllocator<A::Item> = Global> {
     item_accumulator: Vec<A::Item>,
+    pending_buckets: Vec<Bucket<A::Item>>,
+    max_pending: usize,
     bucket_accumulator: A,
     bucket_size: NonZeroUsize,
+    min_bucket_size: Option<NonZeroUsize>,
+    degraded: bool,
+    max_items: Option<NonZeroUsize>,
+    heap_size: Option<fn(&A::Item) -> usize>,
+    heap_bytes: usize,
+    #[cfg(feature = "std")]
+    max_age: Option<Duration>,
+    #[cfg(feature = "std")]
+    oldest: Option<Instant>,
+    allocator: M,
 }
 
 impl<A: BucketAccumulator> Inserter<A> {
     /// Create a new `Inserter` with a default bucket target size
     ///
     /// Create a new `Inserter` for the given `bucket_accumulator`. [Bucket]s
-    /// committed to that [BucketAccumulator] will be of a size near a
+    /// committed to that [BucketAccumulator] will be of a size near the size
+    /// [preferred](BucketAccumulator::preferred_bucket_size) by the accumulator
+    /// or, lacking a preference, a
     /// [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
     pub fn new(bucket_accumulator: A) -> Self {
-        let bucket_size = Self::size_from_bytesize(bucket::DEFAULT_BUCKET_BYTESIZE);
-        Self{item_accumulator: Default::default(), bucket_accumulator, bucket_size}
+        Self::with_allocator(bucket_accumulator, Global)
+    }
+}
+
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> Inserter<A, M> {
+    /// Create a new `Inserter` allocating buckets via the given allocator
+    ///
+    /// Create a new `Inserter` for the given `bucket_accumulator`. The storage
+    /// for [Bucket]s committed to that [BucketAccumulator] will be allocated
+    /// via the given `allocator` and be of a size near the size
+    /// [preferred](BucketAccumulator::preferred_bucket_size) by the accumulator
+    /// or, lacking a preference, a
+    /// [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
+    pub fn with_allocator(bucket_accumulator: A, allocator: M) -> Self {
+        let bucket_size = bucket_accumulator
+            .preferred_bucket_size()
+            .unwrap_or_else(|| Self::size_from_bytesize(bucket::DEFAULT_BUCKET_BYTESIZE));
+        Self{
+            item_accumulator: Default::default(),
+            pending_buckets: Default::default(),
+            max_pending: 0,
+            bucket_accumulator,
+            bucket_size,
+            min_bucket_size: None,
+            degraded: false,
+            max_items: None,
+            heap_size: None,
+            heap_bytes: 0,
+            #[cfg(feature = "std")]
+            max_age: None,
+            #[cfg(feature = "std")]
+            oldest: None,
+            allocator,
+        }
     }
 
     /// Insert items into the accumulator
@@ -148,6 +443,16 @@ impl<A: BucketAccumulator> Inserter<A> {
     /// operation. Thus, callers can recover from allocation failures without
     /// any data loss by passing a mutable reference to an [Iterator] rather
     /// than a value, e.g. the result of [Iterator::by_ref].
+    ///
+    /// If a [minimum bucket size](Self::set_min_bucket_size) is set, a failed
+    /// allocation of a new bucket is retried with successively halved target
+    /// bucket sizes, down to that minimum, before an error is returned.
+    ///
+    /// With [heap accounting](Self::set_heap_accounting) enabled, buckets are
+    /// filled according to the memory owned by the items.
+    ///
+    /// After inserting the items, the partial bucket is
+    /// [flushed](Self::flush_if_due) if due.
     pub fn insert_items(&mut self, items: impl IntoIterator<Item = A::Item>) -> Result<(), InsertionError> {
         let mut items = items.into_iter().fuse();
 
@@ -156,8 +461,27 @@ impl<A: BucketAccumulator> Inserter<A> {
         // creation, as the reallocation might be costly. Shrinking, however,
         // should be unproblematic.
         let bucket_size = self.bucket_size.get();
-        self.item_accumulator.shrink_to(bucket_size);
+        self.allocator.shrink(&mut self.item_accumulator, bucket_size);
+
+        // Give the accumulator a heads-up regarding the buckets to come. This
+        // is merely a hint, the buckets may still be added individually.
+        let expected = (self.item_accumulator.len() + items.size_hint().0) / bucket_size;
+        if expected > 0 {
+            let _ = self.bucket_accumulator.reserve_buckets(expected);
+        }
+
+        let res = match self.heap_size {
+            Some(heap_size) => self.fill_accounted(&mut items, heap_size),
+            None            => self.fill_buckets(&mut items),
+        };
+        self.stamp_oldest();
+        let committed = self.commit_pending();
+        res.and(committed)?;
+        self.flush_if_due()
+    }
 
+    /// Fill buckets with items, holding back full buckets
+    fn fill_buckets(&mut self, items: &mut impl Iterator<Item = A::Item>) -> Result<(), InsertionError> {
         // We first try to fill the current bucket to its capacity.
         let head_room = self.item_accumulator.capacity().saturating_sub(self.item_accumulator.len());
         self.item_accumulator.extend(items.by_ref().take(head_room));
@@ -165,27 +489,467 @@ impl<A: BucketAccumulator> Inserter<A> {
         // As long as we get full buckets worth of items out of the iterator, we
         // have buckets to add to the target buffer.
         while self.item_accumulator.len() >= self.item_accumulator.capacity() {
-            let bucket = Bucket::new(std::mem::take(&mut self.item_accumulator));
-            if bucket.len() > 0 {
-                self.bucket_accumulator.add_bucket(bucket).map_err(|(e, b)| {
-                    self.item_accumulator = b.into_inner();
-                    e
-                })?
+            self.close_bucket()?;
+            self.allocate_accumulator()?;
+            self.item_accumulator.extend(items.by_ref().take(self.item_accumulator.capacity()));
+        }
+
+        Ok(())
+    }
+
+    /// Hold back a full bucket for committing it as part of a batch
+    ///
+    /// If the batch is full or no memory is available for holding back the
+    /// bucket, the batch or the bucket is committed immediately. If batching is
+    /// disabled, the bucket is committed immediately.
+    fn hold_back(&mut self, bucket: Bucket<A::Item>) -> Result<(), InsertionError> {
+        if self.max_pending > 0 && self.pending_buckets.len() >= self.max_pending {
+            if let Err(e) = self.commit_pending() {
+                self.item_accumulator = bucket.into_vec();
+                return Err(e)
             }
+        }
 
-            self.item_accumulator.try_reserve(bucket_size)?;
-            self.item_accumulator.extend(items.by_ref().take(self.item_accumulator.capacity()));
+        if self.max_pending > 0 && self.pending_buckets.try_reserve(1).is_ok() {
+            self.pending_buckets.push(bucket);
+            Ok(())
+        } else {
+            self.bucket_accumulator.add_bucket(bucket).map_err(|(e, b)| {
+                self.item_accumulator = b.into_vec();
+                e
+            })
+        }
+    }
+
+    /// Commit all buckets held back
+    fn commit_pending(&mut self) -> Result<(), InsertionError> {
+        if self.pending_buckets.is_empty() {
+            return Ok(())
+        }
+
+        let pending = &mut self.pending_buckets;
+        let res = self.bucket_accumulator.add_buckets(core::iter::from_fn(|| pending.pop()));
+        res.map_err(|(e, b)| {
+            self.pending_buckets.push(b);
+            e
+        })
+    }
+
+    /// Fill buckets with items, accounting for their heap size
+    fn fill_accounted(
+        &mut self,
+        items: &mut impl Iterator<Item = A::Item>,
+        heap_size: fn(&A::Item) -> usize,
+    ) -> Result<(), InsertionError> {
+        loop {
+            self.make_room()?;
+            match items.next() {
+                Some(item) => {
+                    self.heap_bytes = self.heap_bytes.saturating_add(heap_size(&item));
+                    self.item_accumulator.push(item);
+                },
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Make sure the current bucket can take another item
+    ///
+    /// If the current bucket is full, it is held back and a new one is
+    /// allocated. With heap accounting, a bucket is full once the deep size of
+    /// its items reaches the target bucket size in bytes. Since the memory
+    /// owned by future items is not known in advance, the bucket's storage is
+    /// grown on demand within that budget rather than allocated upfront.
+    fn make_room(&mut self) -> Result<(), InsertionError> {
+        if self.heap_size.is_none() {
+            if self.item_accumulator.len() < self.item_accumulator.capacity() {
+                return Ok(())
+            }
+            self.close_bucket()?;
+            return self.allocate_accumulator()
+        }
+
+        loop {
+            let len = self.item_accumulator.len();
+            let room = self.accounted_capacity().saturating_sub(len);
+            if room > 0 && len < self.item_accumulator.capacity() {
+                return Ok(())
+            }
+
+            if room > 0 {
+                let additional = core::cmp::min(core::cmp::max(len, 1), room);
+                match self.allocator.grow(&mut self.item_accumulator, additional) {
+                    Ok(()) => return Ok(()),
+                    Err(e) if len == 0 => return Err(e),
+                    Err(_) => (),
+                }
+            }
+            self.close_bucket()?;
+        }
+    }
+
+    /// Determine the number of items the current bucket may hold
+    ///
+    /// With heap accounting, the inline size of the items is limited to the
+    /// part of the target bucket size in bytes not used by their heap size.
+    fn accounted_capacity(&self) -> usize {
+        let size = core::cmp::max(core::mem::size_of::<A::Item>(), 1);
+        let budget = self.bucket_size.get().saturating_mul(size).saturating_sub(self.heap_bytes);
+        let capacity = core::cmp::min(budget / size, self.bucket_size.get());
+        self.max_items.map_or(capacity, |m| core::cmp::min(m.get(), capacity))
+    }
+
+    /// Hold back the current bucket unless it is empty
+    ///
+    /// With heap accounting, the bucket's storage is shrunk to its length
+    /// first. The current bucket is left without storage. If holding back the
+    /// bucket fails, it remains the current bucket.
+    fn close_bucket(&mut self) -> Result<(), InsertionError> {
+        let mut acc = core::mem::take(&mut self.item_accumulator);
+        if acc.is_empty() {
+            self.allocator.release(acc);
+            return Ok(())
+        }
+
+        let heap_bytes = self.heap_bytes;
+        self.reset_tracking();
+        if self.heap_size.is_some() {
+            self.allocator.shrink(&mut acc, 0);
+        }
+        self.hold_back(Bucket::from_unsorted(acc)).inspect_err(|_| self.heap_bytes = heap_bytes)
+    }
+
+    /// Insert a single item into the accumulator
+    ///
+    /// This function adds the given `item` to the current bucket. If that
+    /// bucket is already full, it is committed first and a new one is
+    /// allocated. If this fails due to an (re-)allocation failure, an error is
+    /// returned alongside the `item`.
+    ///
+    /// Before the `item` is added, the partial bucket is
+    /// [flushed](Self::flush_if_due) if due.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// let mut inserter = sortbuf::Inserter::new(&mut sortbuf);
+    /// inserter.insert(10).expect("Failed to insert item");
+    /// inserter.insert(20).expect("Failed to insert item");
+    /// drop(inserter);
+    /// assert!(sortbuf.into_iter().eq([20, 10]));
+    /// ```
+    pub fn insert(&mut self, item: A::Item) -> InsertionResult<A::Item> {
+        if let Err(e) = self.flush_if_due() {
+            return Err((e, item))
+        }
+
+        let res = self.make_room();
+        let committed = self.commit_pending();
+        if let Err(e) = res.and(committed) {
+            return Err((e, item))
+        }
+
+        if let Some(heap_size) = self.heap_size {
+            self.heap_bytes = self.heap_bytes.saturating_add(heap_size(&item));
+        }
+        self.item_accumulator.push(item);
+        self.stamp_oldest();
+        Ok(())
+    }
+
+    /// Insert clones of the items in a slice into the accumulator
+    ///
+    /// This function inserts clones of the given `items` into the buffer. If
+    /// the insertion fails due to an (re-)allocation failure, an error is
+    /// returned alongside the items not inserted, i.e. a suffix of `items`.
+    pub fn insert_slice<'s>(&mut self, items: &'s [A::Item]) -> InsertionResult<&'s [A::Item]>
+    where A::Item: Clone,
+    {
+        let mut rest = items.iter();
+        self.insert_items(rest.by_ref().cloned()).map_err(|e| (e, rest.as_slice()))
+    }
+
+    /// Insert items from a fallible source into the accumulator
+    ///
+    /// This function inserts the items yielded by `items` until either the
+    /// [Iterator] is exhausted or it yields an error. In the latter case, the
+    /// source error is returned. All items consumed before that error are
+    /// inserted. If the insertion fails due to an (re-)allocation failure, an
+    /// [InsertionError] is returned unless a source error was encountered as
+    /// well. As with [insert_items](Self::insert_items), all items consumed
+    /// reside either in the underlying [BucketAccumulator] or the `Inserter`s
+    /// internal accumulator after the operation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sortbuf::error::TryInsertError;
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// let mut inserter = sortbuf::Inserter::new(&mut sortbuf);
+    /// let mut lines = ["3", "1", "x", "2"].into_iter().map(str::parse::<u32>);
+    /// let res = inserter.try_insert_items(lines.by_ref());
+    /// assert!(matches!(res, Err(TryInsertError::Source(_))));
+    /// inserter.try_insert_items(lines).expect("Failed to insert items");
+    /// drop(inserter);
+    /// assert!(sortbuf.into_iter().eq([3, 2, 1]));
+    /// ```
+    pub fn try_insert_items<E>(
+        &mut self,
+        items: impl IntoIterator<Item = Result<A::Item, E>>,
+    ) -> Result<(), TryInsertError<E>> {
+        let mut source_err = None;
+        let res = self.insert_items(items.into_iter().map_while(|r| r.map_err(|e| source_err = Some(e)).ok()));
+        match source_err {
+            Some(e) => Err(TryInsertError::Source(e)),
+            None    => res.map_err(Into::into),
+        }
+    }
+
+    /// Insert a [Vec] of items into the accumulator as a single bucket
+    ///
+    /// This function sorts the given `items` in place and commits them to the
+    /// underlying [BucketAccumulator] directly, without copying them and
+    /// regardless of the target bucket size. Items already sorted in ascending
+    /// or descending order are detected cheaply. Items previously inserted
+    /// via other functions are not affected.
+    ///
+    /// If the insertion fails due to an (re-)allocation failure, an error is
+    /// returned alongside the items.
+    pub fn insert_vec(&mut self, items: Vec<A::Item>) -> InsertionResult<Vec<A::Item>> {
+        self.add_vec(Bucket::from_unsorted(items))
+    }
+
+    /// Insert a [Vec] of items sorted in ascending order as a single bucket
+    ///
+    /// This function commits the given `items` to the underlying
+    /// [BucketAccumulator] directly, without copying them and regardless of
+    /// the target bucket size. Items previously inserted via other functions
+    /// are not affected.
+    ///
+    /// The order of the items is verified in a single pass. Should they turn
+    /// out not to be sorted after all, they will be sorted in place.
+    ///
+    /// If the insertion fails due to an (re-)allocation failure, an error is
+    /// returned alongside the items.
+    pub fn insert_sorted_vec(&mut self, items: Vec<A::Item>) -> InsertionResult<Vec<A::Item>> {
+        let bucket = Bucket::from_sorted(items).unwrap_or_else(|mut items| {
+            items.sort_unstable();
+            Bucket::from_sorted_unchecked(items)
+        });
+        self.add_vec(bucket)
+    }
+
+    /// Commit all items accumulated
+    ///
+    /// This function commits all items accumulated in this inserter, including
+    /// those in the partially filled current bucket, to the underlying
+    /// [BucketAccumulator]. Thus, all items inserted so far will be visible in
+    /// the accumulator afterwards. If committing fails due to an
+    /// (re-)allocation failure, an error is returned. In that case, the items
+    /// remain in this inserter.
+    ///
+    /// Note that flushing frequently results in small [Bucket]s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// let mut inserter = sortbuf::Inserter::new(&mut sortbuf);
+    /// inserter.insert_items([10, 20, 5]).expect("Failed to insert items");
+    /// inserter.flush().expect("Failed to flush");
+    /// inserter.insert_items([17]).expect("Failed to insert items");
+    /// drop(inserter);
+    /// assert!(sortbuf.into_iter().eq([20, 17, 10, 5]));
+    /// ```
+    pub fn flush(&mut self) -> Result<(), InsertionError> {
+        self.commit_pending()?;
+        if self.item_accumulator.is_empty() {
+            return Ok(())
         }
 
+        let mut acc = core::mem::take(&mut self.item_accumulator);
+        self.allocator.shrink(&mut acc, 0);
+        self.bucket_accumulator.add_bucket(Bucket::from_unsorted(acc)).map_err(|(e, b)| {
+            self.item_accumulator = b.into_vec();
+            e
+        })?;
+        self.reset_tracking();
         Ok(())
     }
 
+    /// Flush if the maximum number of items or the maximum age is exceeded
+    ///
+    /// This function [flushes](Self::flush) this inserter if the partially
+    /// filled current bucket holds at least the
+    /// [maximum number of items](Self::set_max_items) or, with the `std`
+    /// feature, if its oldest item exceeds the maximum age set via
+    /// `set_max_age`. Callers may call this function
+    /// periodically for inserters which don't receive items regularly.
+    ///
+    /// The age of items is tracked only coarsely: it is measured from the end
+    /// of the call which inserted the first item into the current bucket.
+    pub fn flush_if_due(&mut self) -> Result<(), InsertionError> {
+        if self.flush_due() {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Set the maximum number of items for automatic flushing
+    ///
+    /// If a maximum `items` is set, this inserter will [flush](Self::flush)
+    /// once the partially filled current bucket holds that many items. If
+    /// `None` is given, no automatic flushing based on the number of items will
+    /// happen, which is the default. Buckets allocated after a flush or commit
+    /// are sized for at most `items` items.
+    pub fn set_max_items(&mut self, items: Option<NonZeroUsize>) -> &mut Self {
+        self.max_items = items;
+        self
+    }
+
+    /// Enable or disable accounting for the heap size of items
+    ///
+    /// With heap accounting enabled, a bucket is considered full once the
+    /// [deep size](HeapSize::deep_size) of its items reaches the
+    /// [target bucket size in bytes](Self::bucket_bytesize), rather than only
+    /// once it holds the target number of items. Thus, memory owned by items,
+    /// e.g. the contents of [String](alloc::string::String)s, is not neglected.
+    /// As that memory is not known in advance, a bucket's storage is grown on
+    /// demand within the same budget rather than allocated upfront. Unused
+    /// storage is released before a bucket is committed.
+    ///
+    /// Accounting applies to all items added to the current bucket, e.g. via
+    /// [insert_items](Self::insert_items), [insert](Self::insert) or [Extend].
+    /// Items already in the current bucket when accounting is enabled count
+    /// with their inline size only. Items inserted as a bucket of their own,
+    /// e.g. via [insert_vec](Self::insert_vec), are not affected. By default,
+    /// accounting is disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// let mut counting = sortbuf::adapters::Counting::new(&mut sortbuf);
+    /// let mut inserter = sortbuf::Inserter::new(&mut counting);
+    /// inserter.set_bucket_bytesize(64 * 1024).set_heap_accounting(true);
+    /// inserter.extend((0..64).map(|i| format!("{i:01024}")));
+    /// drop(inserter);
+    /// assert!(counting.buckets() >= 2);
+    /// ```
+    pub fn set_heap_accounting(&mut self, enabled: bool) -> &mut Self
+    where A::Item: HeapSize,
+    {
+        self.heap_size = enabled.then_some(HeapSize::heap_size as fn(&A::Item) -> usize);
+        self
+    }
+
+    /// Set the maximum age of items for automatic flushing
+    ///
+    /// If a maximum `age` is set, this inserter will [flush](Self::flush) once
+    /// the oldest item in the partially filled current bucket is older than
+    /// `age`. If `None` is given, no automatic flushing based on the age of
+    /// items will happen, which is the default.
+    #[cfg(feature = "std")]
+    pub fn set_max_age(&mut self, age: Option<Duration>) -> &mut Self {
+        self.max_age = age;
+        self
+    }
+
+    /// Determine whether a flush is due
+    fn flush_due(&mut self) -> bool {
+        let len = self.item_accumulator.len();
+        if len == 0 {
+            self.reset_tracking();
+            return false
+        }
+
+        if self.max_items.is_some_and(|m| len >= m.get()) {
+            return true
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(max_age) = self.max_age {
+            let now = Instant::now();
+            return now.duration_since(*self.oldest.get_or_insert(now)) >= max_age
+        }
+
+        false
+    }
+
+    /// Record the current time as the age of the current bucket if not done yet
+    ///
+    /// This function is to be called after items were inserted into the
+    /// current bucket.
+    fn stamp_oldest(&mut self) {
+        #[cfg(feature = "std")]
+        if self.max_age.is_some() && self.oldest.is_none() && !self.item_accumulator.is_empty() {
+            self.oldest = Some(Instant::now())
+        }
+    }
+
+    /// Reset the tracked age and heap size of the items in the current bucket
+    fn reset_tracking(&mut self) {
+        self.heap_bytes = 0;
+        #[cfg(feature = "std")]
+        {
+            self.oldest = None;
+        }
+    }
+
+    /// Add a bucket to the underlying accumulator unless it is empty
+    ///
+    /// The bucket's storage is adopted by this inserter's allocator for the
+    /// time it is part of the accumulator.
+    fn add_vec(&mut self, bucket: Bucket<A::Item>) -> InsertionResult<Vec<A::Item>> {
+        if bucket.is_empty() {
+            return Ok(())
+        }
+        if let Err(e) = self.allocator.adopt(bucket.as_vec()) {
+            return Err((e, bucket.into_vec()))
+        }
+        self.bucket_accumulator.add_bucket(bucket).map_err(|(e, b)| {
+            self.allocator.disown(b.as_vec());
+            (e, b.into_vec())
+        })
+    }
+
+    /// Allocate a new, empty item accumulator
+    ///
+    /// The accumulator is sized for the target bucket size or, if less, the
+    /// maximum number of items after which it will be flushed. If a minimum
+    /// bucket size is set, the allocation is retried with halved target bucket
+    /// sizes until it either succeeds or the minimum is reached.
+    fn allocate_accumulator(&mut self) -> Result<(), InsertionError> {
+        loop {
+            let capacity = self.max_items.map_or(self.bucket_size, |m| core::cmp::min(m, self.bucket_size));
+            let err = match self.allocator.allocate(capacity.get()) {
+                Ok(acc) => {
+                    self.item_accumulator = acc;
+                    return Ok(())
+                },
+                Err(e) => e,
+            };
+
+            match self.min_bucket_size {
+                Some(min) if min < self.bucket_size => {
+                    let halved = NonZeroUsize::new(self.bucket_size.get() / 2).unwrap_or(min);
+                    self.bucket_size = core::cmp::max(halved, min);
+                    self.degraded = true;
+                },
+                _ => return Err(err),
+            }
+        }
+    }
+
     /// Set a new target bucket size
     ///
     /// After calling this function, this inserter will commit [Bucket]s
     /// containing near `size` items.
     pub fn set_bucket_size(&mut self, size: NonZeroUsize) -> &mut Self {
         self.bucket_size = size;
+        self.degraded = false;
         self
     }
 
@@ -194,10 +958,57 @@ impl<A: BucketAccumulator> Inserter<A> {
     /// After calling this function, this inserter will commit [Bucket]s near
     /// `bytesize` bytes in size.
     pub fn set_bucket_bytesize(&mut self, bytesize: usize) -> &mut Self {
-        self.bucket_size = Self::size_from_bytesize(bytesize);
+        self.set_bucket_size(Self::size_from_bytesize(bytesize))
+    }
+
+    /// Set a minimum bucket size for retrying failed allocations
+    ///
+    /// If a minimum `size` is set, this inserter will retry failed allocations
+    /// of new buckets with successively halved target bucket sizes, down to
+    /// the given minimum. Buckets committed will then contain fewer items than
+    /// originally targeted. If `None` is given, allocation failures are always
+    /// reported to the caller immediately, which is the default.
+    pub fn set_min_bucket_size(&mut self, size: Option<NonZeroUsize>) -> &mut Self {
+        self.min_bucket_size = size;
+        self
+    }
+
+    /// Set the maximum number of full buckets to hold back
+    ///
+    /// If a maximum of more than zero `buckets` is set, this inserter will hold
+    /// back up to that many full [Bucket]s and commit them in batches via
+    /// [BucketAccumulator::add_buckets]. This amortizes per-call costs of the
+    /// accumulator, e.g. acquiring a lock, but delays the visibility of the
+    /// items in the accumulator, e.g. for
+    /// [SortBuf::drain_from](super::SortBuf::drain_from) or for consumers
+    /// applying backpressure. Held back buckets are committed at the latest
+    /// when the inserting function returns. If zero is given, full buckets are
+    /// committed immediately, which is the default.
+    pub fn set_max_pending_buckets(&mut self, buckets: usize) -> &mut Self {
+        self.max_pending = buckets;
         self
     }
 
+    /// Determine whether the target bucket size was reduced
+    ///
+    /// This function returns `true` if this inserter reduced its target bucket
+    /// size due to allocation failures since the target bucket size was last
+    /// set. The current target bucket size can be retrieved via
+    /// [bucket_size](Self::bucket_size).
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Retrieve a reference to the underlying [BucketAccumulator]
+    pub fn bucket_accumulator(&self) -> &A {
+        &self.bucket_accumulator
+    }
+
+    /// Retrieve a mutable reference to the underlying [BucketAccumulator]
+    pub fn bucket_accumulator_mut(&mut self) -> &mut A {
+        &mut self.bucket_accumulator
+    }
+
     /// Get the current target bucket size in items
     pub fn bucket_size(&self) -> NonZeroUsize {
         self.bucket_size
@@ -205,46 +1016,48 @@ impl<A: BucketAccumulator> Inserter<A> {
 
     /// Get the current target bucket size in bytes
     pub fn bucket_bytesize(&self) -> usize {
-        self.bucket_size.get() * std::mem::size_of::<A::Item>()
+        self.bucket_size.get() * core::mem::size_of::<A::Item>()
     }
 
     /// Determine the bucket target size for a given bytesize
     fn size_from_bytesize(bytesize: usize) -> NonZeroUsize {
-        NonZeroUsize::new(bytesize / std::mem::size_of::<A::Item>())
+        NonZeroUsize::new(bytesize / core::cmp::max(core::mem::size_of::<A::Item>(), 1))
             .or(NonZeroUsize::new(1))
             .expect("Could not compute bucket size")
     }
 }
 
-impl<A: BucketAccumulator<Item = std::cmp::Reverse<T>>, T: Ord> Inserter<A> {
+impl<A, M, T> Inserter<A, M>
+where
+    A: BucketAccumulator<Item = core::cmp::Reverse<T>>,
+    M: BucketAllocator<A::Item>,
+    T: Ord,
+{
     /// Insert reversed items into the accumulator
     ///
     /// This function inserts the given `items` to the buffer, each wrapped in
-    /// a [std::cmp::Reverse]. If the insertion fails due to an (re-)allocation
+    /// a [core::cmp::Reverse]. If the insertion fails due to an (re-)allocation
     /// failure, an error is returned alongside an iterator over those items
     /// that were not inserted.
     pub fn insert_items_reversed(
         &mut self,
         items: impl IntoIterator<Item = T>,
     ) -> Result<(), InsertionError> {
-        self.insert_items(items.into_iter().map(std::cmp::Reverse))
+        self.insert_items(items.into_iter().map(core::cmp::Reverse))
     }
 }
 
-impl<A: BucketAccumulator> Extend<A::Item> for Inserter<A> {
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> Extend<A::Item> for Inserter<A, M> {
     fn extend<I: IntoIterator<Item = A::Item>>(&mut self, iter: I) {
         self.insert_items(iter).expect("Failed to insert items")
     }
 }
 
-impl<A: BucketAccumulator> Drop for Inserter<A> {
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> Drop for Inserter<A, M> {
     fn drop(&mut self) {
-        let acc = std::mem::take(&mut self.item_accumulator);
-        if !acc.is_empty() {
-            self.bucket_accumulator
-                .add_bucket(Bucket::new(acc))
-                .expect("Failed to add final bucket")
-        }
+        self.flush().expect("Failed to add final bucket");
+        self.allocator.release(core::mem::take(&mut self.item_accumulator));
+        self.bucket_accumulator.on_inserter_finished()
     }
 }
 
@@ -1,14 +1,20 @@
 // SPDX-License-Identifier: MIT
 //! [Iter] type and related utilities
 
-use std::collections::binary_heap::{self, BinaryHeap};
-use std::iter::FusedIterator;
+use alloc::collections::binary_heap::{self, BinaryHeap};
+use alloc::vec::Vec;
+use core::iter::FusedIterator;
 
+use super::{Bucket, BucketAccumulator, BucketAllocator, Global, SortBuf};
 use super::bucket::SortedBucket;
+use super::error::{InsertionError, InsertionResult};
 
 
 /// Default shrinking theshold in bytes
-const DEFAULT_SHRINK_THRESHOLD_BYTES: usize = 1024*1024;
+pub(crate) const DEFAULT_SHRINK_THRESHOLD_BYTES: usize = 1024*1024;
+
+/// Size of the batches in which single items pushed are collected, in bytes
+const PUSH_BATCH_BYTESIZE: usize = 1024*1024;
 
 
 /// [Iterator] yielding items in descending order
@@ -19,7 +25,38 @@ const DEFAULT_SHRINK_THRESHOLD_BYTES: usize = 1024*1024;
 /// The iterator will release memory from time to time during iteration. The
 /// specifics are controlled via an internal threshold which can be altered
 /// through [Iter::with_shrink_threshold] and
-/// [Iter::with_shrink_threshold_bytes].
+/// [Iter::with_shrink_threshold_bytes]. On Linux, the memory backing consumed
+/// items is released in place, without moving any items. Thus, releasing memory
+/// during iteration does not temporarily increase memory usage. Shrinking and
+/// releasing the storage of buckets is done through a [BucketAllocator], which
+/// is inherited from the originating [SortBuf] and may be replaced via
+/// [Iter::with_allocator].
+///
+/// New items may be added while iterating, either as whole [Bucket]s via
+/// [Iter::push_bucket] or one at a time via [Iter::push]. Thus, an [Iter] may
+/// serve as a priority queue which new items are added to while old items are
+/// drained. This type also implements [BucketAccumulator], which allows
+/// feeding it via [Inserter](super::Inserter)s. As with [Iter::push_bucket],
+/// the storage of buckets added that way is [adopted](BucketAllocator::adopt)
+/// by the iterator's allocator. Hence, [Inserter](super::Inserter)s feeding an
+/// [Iter] should use the [Global] allocator. An [Iter] may be converted back
+/// into a [SortBuf] using the same allocator.
+///
+/// # Examples
+///
+/// ```
+/// let mut sortbuf = sortbuf::SortBuf::new();
+/// sortbuf::Inserter::new(&mut sortbuf).insert_items([4, 1, 3]).expect("Failed to insert items");
+///
+/// let mut queue = sortbuf.into_iter();
+/// assert_eq!(queue.next(), Some(4));
+/// queue.push(5).expect("Failed to push item");
+/// queue.push(2).expect("Failed to push item");
+/// assert_eq!(queue.next(), Some(5));
+///
+/// let sortbuf: sortbuf::SortBuf<_> = queue.into();
+/// assert!(sortbuf.into_iter().eq([3, 2, 1]));
+/// ```
 ///
 /// # Time complexity
 ///
@@ -34,12 +71,24 @@ const DEFAULT_SHRINK_THRESHOLD_BYTES: usize = 1024*1024;
 /// The omission of an implementation of [Clone] for this type is on purpose, as
 /// it is meant for large amounts of data.
 #[derive(Debug)]
-pub struct Iter<T: Ord> {
+pub struct Iter<T: Ord, M: BucketAllocator<T> = Global> {
     buckets: BinaryHeap<SortedBucket<T>>,
+    pending: BinaryHeap<T>,
     shrink_theshold: usize,
+    allocator: M,
 }
 
-impl<T: Ord> Iter<T> {
+impl<T: Ord, M: BucketAllocator<T>> Iter<T, M> {
+    /// Create an iterator over the items in the given buckets
+    pub(crate) fn new(buckets: Vec<SortedBucket<T>>, allocator: M) -> Self {
+        Self{
+            buckets: buckets.into(),
+            pending: Default::default(),
+            shrink_theshold: DEFAULT_SHRINK_THRESHOLD_BYTES / core::cmp::max(core::mem::size_of::<T>(), 1),
+            allocator,
+        }
+    }
+
     /// Set the number of unused item slots buckets are allowed to accumulate
     ///
     /// This iterator pulls items from a number of buckets, which will thus
@@ -48,8 +97,9 @@ impl<T: Ord> Iter<T> {
     /// thus make memory availible again.
     ///
     /// This function allows specifying the shrinking threshold.
-    pub fn with_shrink_threshold(self, shrink_theshold: usize) -> Self {
-        Self{shrink_theshold, ..self}
+    pub fn with_shrink_threshold(mut self, shrink_theshold: usize) -> Self {
+        self.shrink_theshold = shrink_theshold;
+        self
     }
 
     /// Set the number of unused bytes buckets are allowed to accumulate
@@ -61,35 +111,218 @@ impl<T: Ord> Iter<T> {
     ///
     /// This function allows specifying the shrinking threshold, in bytes.
     pub fn with_shrink_threshold_bytes(self, shrink_theshold: usize) -> Self {
-        self.with_shrink_threshold(shrink_theshold / std::mem::size_of::<T>())
+        self.with_shrink_threshold(shrink_theshold / core::mem::size_of::<T>())
+    }
+
+    /// Set the [BucketAllocator] used for shrinking and releasing buckets
+    ///
+    /// This iterator will shrink the storage of buckets and release the
+    /// storage of exhausted buckets through the given `allocator`. This is
+    /// necessary for allocators tracking the memory used, such as
+    /// [MemoryLimit](super::MemoryLimit), as well as allocators recycling
+    /// bucket storage.
+    pub fn with_allocator<N: BucketAllocator<T>>(mut self, allocator: N) -> Iter<T, N> {
+        Iter{
+            buckets: core::mem::take(&mut self.buckets),
+            pending: core::mem::take(&mut self.pending),
+            shrink_theshold: self.shrink_theshold,
+            allocator,
+        }
+    }
+
+    /// Retrieve the next run of items
+    ///
+    /// This function yields the longest run of items the bucket holding the
+    /// current greatest item can contribute before any other bucket holds the
+    /// greatest item. The run is returned as a [Vec] of items in descending
+    /// order. The concatenation of all runs yields the same items in the same
+    /// order as [Iterator::next] would, up to the order of equal items.
+    ///
+    /// # Time complexity
+    ///
+    /// Retrieving a run of _r_ items has a runtime cost of O(log(_n_/_b_) +
+    /// log(_b_) + _r_). For skewed or partially sorted inputs, which result in
+    /// long runs, this is considerably cheaper than retrieving the same items
+    /// via [Iterator::next] one at a time.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails. Use
+    /// [try_next_batch](Self::try_next_batch) for handling allocation failures.
+    pub fn next_batch(&mut self) -> Option<Vec<T>> {
+        self.try_next_batch().expect("Failed to allocate run")
+    }
+
+    /// Retrieve the next run of items
+    ///
+    /// This function behaves like [next_batch](Self::next_batch). However, if
+    /// allocating the storage for a run fails, an error is returned. In that
+    /// case, no items are removed.
+    ///
+    /// If a run comprises all remaining items of a bucket, the bucket's storage
+    /// is reused for the run. Otherwise, storage for exactly the run's items is
+    /// allocated via this iterator's [BucketAllocator]. In either case, the
+    /// run's storage is [disowned](BucketAllocator::disown) by the allocator.
+    pub fn try_next_batch(&mut self) -> Result<Option<Vec<T>>, InsertionError> {
+        let top = self.buckets.peek().and_then(SortedBucket::peek);
+        if self.pending.peek().is_some_and(|p| Some(p) >= top) {
+            let mut run = Vec::new();
+            while self.pending.peek().is_some_and(|p| Some(p) >= top) {
+                // A partial run is still a valid run.
+                match run.try_reserve(1) {
+                    Ok(()) => run.extend(self.pending.pop()),
+                    Err(_) if !run.is_empty() => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            return Ok(Some(run))
+        }
+
+        while let Some(mut bucket) = self.buckets.pop() {
+            let bound = core::cmp::max(self.buckets.peek().and_then(SortedBucket::peek), self.pending.peek());
+            let run = match bucket.split_off_from(bound, &self.allocator) {
+                Ok(run) => run,
+                Err(e) => {
+                    self.buckets.push(bucket);
+                    return Err(e)
+                },
+            };
+            self.allocator.disown(&run);
+            if bucket.len() > 0 {
+                if bucket.overcapacity() >= self.shrink_theshold {
+                    bucket.shink_to_fit(&self.allocator)
+                }
+                self.buckets.push(bucket);
+            }
+
+            if !run.is_empty() {
+                return Ok(Some(run))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Create an [Iterator] yielding runs of items
+    ///
+    /// The [Iterator] returned yields the runs retrieved via
+    /// [next_batch](Self::next_batch) until this iterator is drained.
+    pub fn drain_runs(&mut self) -> Runs<'_, T, M> {
+        Runs(self)
+    }
+
+    /// Add a [Bucket] of items
+    ///
+    /// The items in the given `bucket` will be yielded alongside the items
+    /// already present, in order. The bucket's storage is
+    /// [adopted](BucketAllocator::adopt) by this iterator's [BucketAllocator],
+    /// which will eventually release it. If the [Bucket] could not be added, an
+    /// error is returned alongside the [Bucket].
+    ///
+    /// # Time complexity
+    ///
+    /// Adding a [Bucket] has a runtime cost of O(log(_n_/_b_)).
+    pub fn push_bucket(&mut self, bucket: Bucket<T>) -> InsertionResult<Bucket<T>> {
+        if bucket.is_empty() {
+            return Ok(())
+        }
+
+        if let Err(e) = self.buckets.try_reserve(1) {
+            return Err((e.into(), bucket))
+        }
+        if let Err(e) = self.allocator.adopt(bucket.as_vec()) {
+            return Err((e, bucket))
+        }
+        self.buckets.push(bucket.into());
+        Ok(())
+    }
+
+    /// Add a single item
+    ///
+    /// The given `item` will be yielded alongside the items already present,
+    /// in order. Items added via this function are collected in a batch which
+    /// is turned into a bucket once it reaches a size of about 1MiB. At that
+    /// point, the batch's storage is [adopted](BucketAllocator::adopt) by this
+    /// iterator's [BucketAllocator]. If the item could not be added, an error
+    /// is returned alongside the item.
+    ///
+    /// # Time complexity
+    ///
+    /// Adding an item has an amortized runtime cost of O(log(_p_)), with _p_
+    /// denoting the number of items in the current batch.
+    pub fn push(&mut self, item: T) -> Result<(), (InsertionError, T)> {
+        let batch_size = PUSH_BATCH_BYTESIZE / core::cmp::max(core::mem::size_of::<T>(), 1);
+        if self.pending.len() >= batch_size {
+            if let Err(e) = self.buckets.try_reserve(1) {
+                return Err((e.into(), item))
+            }
+            let items = core::mem::take(&mut self.pending).into_sorted_vec();
+            if let Err(e) = self.allocator.adopt(&items) {
+                self.pending = items.into();
+                return Err((e, item))
+            }
+            self.buckets.push(Bucket::from_sorted_unchecked(items).into());
+        }
+
+        match self.pending.try_reserve(1) {
+            Ok(_)   => {
+                self.pending.push(item);
+                Ok(())
+            },
+            Err(e)  => Err((e.into(), item)),
+        }
+    }
+
+    /// Convert this iterator into its allocator, dropping all remaining items
+    fn into_allocator(self) -> M {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        let Self{buckets, pending, allocator, ..} = &mut *this;
+        buckets.drain().for_each(|b| allocator.release(b.into_inner()));
+        // SAFETY: `this` is never dropped. Instead, we drop the fields other
+        //         than the allocator in place and move the allocator out.
+        //         None of them is used afterwards.
+        unsafe {
+            core::ptr::drop_in_place(buckets);
+            core::ptr::drop_in_place(pending);
+            core::ptr::read(allocator)
+        }
+    }
+
+    /// Retrieve the total overcapacity of all buckets
+    #[cfg(test)]
+    pub(crate) fn overcapacity(&self) -> usize {
+        self.buckets.iter().map(SortedBucket::overcapacity).sum()
     }
 }
 
 impl<T: Ord> From<Vec<SortedBucket<T>>> for Iter<T> {
     fn from(buckets: Vec<SortedBucket<T>>) -> Self {
-        Self{
-            buckets: buckets.into(),
-            shrink_theshold: DEFAULT_SHRINK_THRESHOLD_BYTES / std::mem::size_of::<T>(),
-        }
+        Self::new(buckets, Global)
     }
 }
 
-impl<T: Ord> ExactSizeIterator for Iter<T> {}
+impl<T: Ord, M: BucketAllocator<T>> ExactSizeIterator for Iter<T, M> {}
 
-impl<T: Ord> FusedIterator for Iter<T> {}
+impl<T: Ord, M: BucketAllocator<T>> FusedIterator for Iter<T, M> {}
 
-impl<T: Ord> Iterator for Iter<T> {
+impl<T: Ord, M: BucketAllocator<T>> Iterator for Iter<T, M> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.pending.peek() {
+            if Some(item) >= self.buckets.peek().and_then(SortedBucket::peek) {
+                return self.pending.pop()
+            }
+        }
+
         while let Some(mut bucket) = self.buckets.peek_mut() {
             if let Some(item) = bucket.next() {
                 if bucket.overcapacity() >= self.shrink_theshold {
-                    bucket.shink_to_fit()
+                    bucket.shink_to_fit(&self.allocator)
                 }
                 return Some(item)
             } else {
-                binary_heap::PeekMut::pop(bucket);
+                self.allocator.release(binary_heap::PeekMut::pop(bucket).into_inner());
             }
         }
 
@@ -97,8 +330,75 @@ impl<T: Ord> Iterator for Iter<T> {
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let size = self.buckets.iter().map(ExactSizeIterator::len).sum();
+        let size = self.buckets.iter().map(ExactSizeIterator::len).sum::<usize>() + self.pending.len();
         (size, Some(size))
     }
 }
 
+impl<T: Ord, M: BucketAllocator<T>> BucketAccumulator for Iter<T, M> {
+    type Item = T;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        self.push_bucket(bucket)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.buckets.try_reserve(additional).map_err(Into::into)
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<T>> From<Iter<T, M>> for SortBuf<T, M> {
+    /// Convert the remaining items back into a [SortBuf]
+    ///
+    /// The [SortBuf] uses the iterator's [BucketAllocator]. Items added via
+    /// [Iter::push] which were not yet turned into a bucket are turned into one,
+    /// which is [adopted](BucketAllocator::adopt) by the allocator.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails or the allocator refuses to
+    /// adopt the remaining items added via [Iter::push].
+    fn from(mut iter: Iter<T, M>) -> Self {
+        let mut buckets = core::mem::take(&mut iter.buckets).into_vec();
+        buckets.retain(|b| b.len() > 0);
+        let pending = core::mem::take(&mut iter.pending);
+
+        let mut buf = Self::with_allocator(iter.into_allocator());
+        buf.buckets = buckets;
+        if !pending.is_empty() {
+            let items = pending.into_sorted_vec();
+            buf.allocator.adopt(&items).expect("Failed to adopt items");
+            buf.buckets.push(Bucket::from_sorted_unchecked(items).into());
+        }
+        buf
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<T>> Drop for Iter<T, M> {
+    fn drop(&mut self) {
+        self.buckets.drain().for_each(|b| self.allocator.release(b.into_inner()))
+    }
+}
+
+
+/// [Iterator] yielding runs of items in descending order
+///
+/// Instances of this type are created via [Iter::drain_runs]. Each item yielded
+/// is a [Vec] of items in descending order.
+#[derive(Debug)]
+pub struct Runs<'a, T: Ord, M: BucketAllocator<T> = Global>(&'a mut Iter<T, M>);
+
+impl<T: Ord, M: BucketAllocator<T>> FusedIterator for Runs<'_, T, M> {}
+
+impl<T: Ord, M: BucketAllocator<T>> Iterator for Runs<'_, T, M> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_batch()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.len();
+        (core::cmp::min(len, 1), Some(len))
+    }
+}
@@ -24,7 +24,7 @@
 //! assert!(sortbuf.into_iter().eq([20, 17, 10, 5]));
 //! ```
 //!
-//! For ascending iteration, items need to be wrapped in [std::cmp::Reverse].
+//! For ascending iteration, items need to be wrapped in [core::cmp::Reverse].
 //! However, the library provides convenience functions for handling the
 //! (un)wrapping:
 //!
@@ -36,9 +36,25 @@
 //! assert!(sortbuf.unreversed().eq([5, 10, 17, 20]));
 //! ```
 //!
-//! Multithreaded insertion is supported via multiple [Inserter]s:
+//! Multithreaded insertion is supported via multiple [Inserter]s. The simplest
+//! way is to let `SortBuf::fill_parallel` spawn scoped threads:
 //!
 //! ```
+//! # #[cfg(feature = "std")] {
+//! # use std::num::NonZeroUsize;
+//! let sortbuf = sortbuf::SortBuf::new().fill_parallel(
+//!     NonZeroUsize::new(4).unwrap(),
+//!     |inserter, n| inserter.insert_items((0..1000).map(|i| 4*i + n)),
+//! ).expect("Failed to insert items");
+//! assert!(sortbuf.into_iter().eq((0..4000).rev()));
+//! # }
+//! ```
+//!
+//! Alternatively, [Inserter]s may be created for a shared buffer, e.g. for
+//! threads outliving the current scope:
+//!
+//! ```
+//! # #[cfg(feature = "std")] {
 //! use std::sync::{Arc, Mutex};
 //! let sortbuf: Arc<Mutex<sortbuf::SortBuf<_>>> = Default::default();
 //! let workers: Vec<_> = (0..4).map(|n| {
@@ -49,8 +65,40 @@
 //! }).collect();
 //! workers.into_iter().try_for_each(|h| h.join()).unwrap();
 //! assert!(sortbuf.lock().unwrap().take().into_iter().eq((0..4000).rev()));
+//! # }
+//! ```
+//!
+//! For simply sorting the items of an [Iterator], the [SortBufIteratorExt]
+//! trait provides shorthands:
+//!
+//! ```
+//! use sortbuf::SortBufIteratorExt;
+//! assert!([10, 20, 5, 17].into_iter().sortbuf_sorted().eq([5, 10, 17, 20]));
 //! ```
 //!
+//! Key-value pairs may be sorted by their keys only via a [SortMap], which also
+//! allows combining values of equal keys:
+//!
+//! ```
+//! let mut map = sortbuf::SortMap::with_combiner(|a: u32, b| a + b);
+//! let mut inserter = sortbuf::Inserter::new(&mut map);
+//! inserter.insert_pairs([(1, 10), (2, 20), (1, 5)]).expect("Failed to insert items");
+//! drop(inserter);
+//! assert!(map.into_iter().eq([(2, 20), (1, 15)]));
+//! ```
+//!
+//! # Features
+//!
+//! The library's core data structures only require [alloc] and are thus usable
+//! in `#![no_std]` environments. The following features are available:
+//!
+//! * `std` (default): enables [BucketAccumulator] implementations for
+//!   synchronization primitives such as `Mutex` and `RwLock`, which are
+//!   required for multi-threaded insertion, as well as utilities for
+//!   multi-threaded processing of items such as
+//!   `SortBuf::for_each_chunk_ordered` and for passing [Bucket]s through
+//!   channels, such as `Collector`.
+//!
 //! # Approach and comparison
 //!
 //! As indicated in the examples above, adding new items to a buffer is done via
@@ -90,24 +138,56 @@
 //! ## Comparison to BTreeSet
 //!
 //! Another option for sorting items without the need for a separate sorting
-//! phase would be an [BTreeSet](std::collections::BTreeSet). Contrary to the
+//! phase would be an [BTreeSet](alloc::collections::BTreeSet). Contrary to the
 //! `sortbuf` approach, most of the time is spent in the insertion phase rather
-//! than the iteration phase. Using a [BTreeSet](std::collections::BTreeSet) is
+//! than the iteration phase. Using a [BTreeSet](alloc::collections::BTreeSet) is
 //! usually slower than a [SortBuf] with sufficiently large [Bucket]s, not
 //! parallelizable and incurs a higher memory overhead.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+mod allocator;
 mod bucket;
+#[cfg(feature = "std")]
+mod channel;
+mod ext;
 mod inserter;
 mod iter;
+mod map;
+#[cfg(feature = "std")]
+mod parallel;
+mod partition;
+mod record;
+mod selection;
+mod size;
 
+pub mod adapters;
 pub mod error;
 
 #[cfg(test)]
 mod tests;
 
 
+pub use allocator::{BucketAllocator, Global};
+#[cfg(feature = "std")]
+pub use allocator::BucketPool;
+#[cfg(target_has_atomic = "ptr")]
+pub use allocator::MemoryLimit;
 pub use bucket::{Bucket, DEFAULT_BUCKET_BYTESIZE};
+#[cfg(feature = "std")]
+pub use channel::{collect_buckets, Collected, Collector};
+pub use ext::SortBufIteratorExt;
 pub use inserter::{BucketAccumulator, Inserter};
+pub use map::{KeyValue, SortMap};
+pub use partition::PartitionedInserter;
+pub use record::{IntoRecords, RecordAccumulator, RecordBucket, RecordBuf, RecordInserter, Records};
+pub use selection::RunInserter;
+pub use size::HeapSize;
+
+
+use alloc::vec::Vec;
 
 
 /// Data structure for preparing a large number of items for sorted iteration
@@ -118,7 +198,7 @@ pub use inserter::{BucketAccumulator, Inserter};
 /// inserted, [IntoIterator] may be used for iterating over these items in
 /// descending order (according to the items' implementation of [Ord]).
 ///
-/// For ascending iteration, users need to wrap items in [std::cmp::Reverse] and
+/// For ascending iteration, users need to wrap items in [core::cmp::Reverse] and
 /// unwrap them during the final iteration.
 ///
 /// # Time complexity
@@ -141,48 +221,267 @@ pub use inserter::{BucketAccumulator, Inserter};
 ///
 /// # Other notes
 ///
+/// Buckets are grown, shrunk and released through a [BucketAllocator], which
+/// may be set via [with_allocator](Self::with_allocator) and is passed on to
+/// the [Iterator]s created from the buffer. By default, the [Global] allocator
+/// is used.
+///
 /// The omission of an implementation of [Clone] for this type is on purpose, as
 /// it is meant for large amounts of data.
 #[derive(Debug)]
-pub struct SortBuf<T: Ord> {
+pub struct SortBuf<T: Ord, M: BucketAllocator<T> = Global> {
     buckets: Vec<bucket::SortedBucket<T>>,
+    allocator: M,
 }
 
 impl<T: Ord> SortBuf<T> {
     /// Create a new sorting buffer
     pub fn new() -> Self {
-        Self {buckets: Vec::new()}
+        Self::with_allocator(Global)
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<T>> SortBuf<T, M> {
+    /// Create a new sorting buffer using the given [BucketAllocator]
+    ///
+    /// The buffer will grow, shrink and release the storage of its buckets
+    /// through the given `allocator`, which is passed on to any [Iterator]
+    /// created from the buffer. [Inserter]s filling the buffer should use the
+    /// same allocator.
+    pub fn with_allocator(allocator: M) -> Self {
+        Self{buckets: Vec::new(), allocator}
+    }
+
+    /// Retrieve the [BucketAllocator] used by this buffer
+    pub fn allocator(&self) -> &M {
+        &self.allocator
     }
 
     /// Take this buffer's contents, leaving an empty buffer
-    pub fn take(&mut self) -> Self {
-        std::mem::take(self)
+    pub fn take(&mut self) -> Self where M: Clone {
+        Self{buckets: core::mem::take(&mut self.buckets), allocator: self.allocator.clone()}
+    }
+
+    /// Convert into a [Vec] of all items in ascending order
+    ///
+    /// This function merges all buckets pairwise until only one remains,
+    /// reusing the allocation of the bigger bucket in each step. Storage is
+    /// grown and released through this buffer's [BucketAllocator] and the
+    /// storage of the [Vec] returned is [disowned](BucketAllocator::disown)
+    /// by it. Compared to
+    /// collecting the items from this buffer's [Iterator], this is usually
+    /// considerably faster if all items are needed in memory anyway. Note that
+    /// the items are in _ascending_ order, i.e. the reverse order in which they
+    /// would be yielded by this buffer's [Iterator], which allows using
+    /// functions such as [slice::binary_search] on the result.
+    ///
+    /// If an allocation fails during the process, an error is returned
+    /// alongside a buffer holding all the items.
+    ///
+    /// # Time complexity
+    ///
+    /// With _n_ denoting the number of items and _b_ denoting the bucket size,
+    /// the estimated runtime cost of this function is O(_n_ log(_n_/_b_)).
+    /// However, it does not suffer from the cache-misses affecting iteration.
+    pub fn into_sorted_vec(self) -> Result<Vec<T>, (error::InsertionError, Self)> {
+        let (mut buckets, allocator) = self.into_parts();
+
+        while buckets.len() > 1 {
+            let len = buckets.len();
+            for k in 0..len / 2 {
+                let a = core::mem::take(&mut buckets[2*k]);
+                let b = core::mem::take(&mut buckets[2*k + 1]);
+                match a.merge(b, &allocator) {
+                    Ok(merged) => buckets[k] = merged,
+                    Err((e, a, b)) => {
+                        buckets[2*k] = a;
+                        buckets[2*k + 1] = b;
+                        buckets.retain(|b| b.len() > 0);
+                        return Err((e, Self{buckets, allocator}))
+                    },
+                }
+            }
+            buckets.swap(len / 2, len - 1);
+            buckets.truncate(len.div_ceil(2));
+        }
+
+        let items = buckets.pop().map(bucket::SortedBucket::into_inner).unwrap_or_default();
+        allocator.disown(&items);
+        Ok(items)
+    }
+
+    /// Remove all items greater than or equal to `bound`
+    ///
+    /// This function removes all items not less than the given `bound` from
+    /// this buffer and returns an [Iterator] yielding them in descending order.
+    /// All other items remain in this buffer. Contrary to [IntoIterator], this
+    /// allows consuming items from a buffer while it still receives new items.
+    ///
+    /// Unless all items of a bucket are removed, the items removed from it are
+    /// moved to new storage allocated via this buffer's [BucketAllocator],
+    /// which is also used by the [Iterator] returned. If an allocation fails,
+    /// an error is returned and the buffer is left unaltered.
+    ///
+    /// # Time complexity
+    ///
+    /// With _n_ denoting the number of items and _b_ denoting the bucket size,
+    /// locating the items to remove comes with a run-time cost of
+    /// O(_n_/_b_ log(_b_)). Removing _r_ items costs O(_r_).
+    pub fn drain_from(&mut self, bound: &T) -> Result<iter::Iter<T, M>, error::InsertionError>
+    where M: Clone,
+    {
+        self.drain_upper(|i| i < bound)
+    }
+
+    /// Remove the upper part of all buckets
+    ///
+    /// This function removes all items for which `keep` returns `false` from
+    /// this buffer. `keep` must return `true` for a (possibly empty) prefix of
+    /// the items in ascending order. If an allocation fails, all items are put
+    /// back and an error is returned.
+    fn drain_upper(
+        &mut self,
+        mut keep: impl FnMut(&T) -> bool,
+    ) -> Result<iter::Iter<T, M>, error::InsertionError> where M: Clone {
+        let shrink_threshold = iter::DEFAULT_SHRINK_THRESHOLD_BYTES / core::cmp::max(core::mem::size_of::<T>(), 1);
+
+        let mut drained = Vec::new();
+        drained.try_reserve_exact(self.buckets.len())?;
+        for bucket in &mut self.buckets {
+            match bucket.split_upper(&mut keep, &self.allocator) {
+                Ok(upper) => drained.push(upper),
+                Err(e) => {
+                    self.buckets.iter_mut().zip(drained).for_each(|(b, u)| b.rejoin(u));
+                    return Err(e)
+                },
+            }
+        }
+
+        for bucket in &mut self.buckets {
+            if bucket.overcapacity() >= shrink_threshold {
+                bucket.shink_to_fit(&self.allocator)
+            }
+        }
+        self.buckets.retain(|b| b.len() > 0);
+        drained.retain(|b| b.len() > 0);
+
+        Ok(iter::Iter::new(drained, self.allocator.clone()))
+    }
+
+    /// Split this buffer into its buckets and its allocator
+    fn into_parts(self) -> (Vec<bucket::SortedBucket<T>>, M) {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        let buckets = core::mem::take(&mut this.buckets);
+        // SAFETY: `this` is neither dropped nor used after this point. Hence,
+        //         the allocator is moved out exactly once.
+        let allocator = unsafe { core::ptr::read(&this.allocator) };
+        (buckets, allocator)
     }
 }
 
-impl<T: Ord> Default for SortBuf<T> {
+impl<T: Ord, M: BucketAllocator<T> + Default> Default for SortBuf<T, M> {
     fn default() -> Self {
-        Self::new()
+        Self::with_allocator(Default::default())
     }
 }
 
-impl<T: Ord> SortBuf<std::cmp::Reverse<T>> {
-    /// Convert into an [Iterator] over items unwrapped from [std::cmp::Reverse]
+impl<T: Ord> FromIterator<T> for SortBuf<T> {
+    /// Create a buffer holding all items of the given [Iterator]
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut buf = Self::new();
+        buf.extend(iter);
+        buf
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<T> + Clone> Extend<T> for SortBuf<T, M> {
+    /// Insert all items of the given [Iterator]
+    ///
+    /// The items are inserted via a temporary [Inserter] with the default
+    /// bucket size, using this buffer's [BucketAllocator]. Since that [Inserter] commits its remaining items as a
+    /// bucket, repeatedly extending a buffer by few items will result in many
+    /// small buckets. Use a dedicated [Inserter] in such cases.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if an allocation fails.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let allocator = self.allocator.clone();
+        Inserter::with_allocator(self, allocator).insert_items(iter).expect("Failed to insert items")
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<core::cmp::Reverse<T>>> SortBuf<core::cmp::Reverse<T>, M> {
+    /// Convert into an [Iterator] over items unwrapped from [core::cmp::Reverse]
     ///
     /// This funtion allows convenient retrieval of the buffered items in their
     /// unreversed order. Use this function if you need an iterator over items
     /// in ascending order.
     pub fn unreversed(self) -> impl Iterator<Item = T> {
-        self.into_iter().map(|std::cmp::Reverse(v)| v)
+        self.into_iter().map(|core::cmp::Reverse(v)| v)
+    }
+
+    /// Remove all items less than `watermark`
+    ///
+    /// This function removes all items less than the given `watermark` from
+    /// this buffer and returns an [Iterator] yielding them unwrapped and in
+    /// ascending order. All other items remain in this buffer. This allows
+    /// using a buffer for reordering a stream of items, e.g. events ordered by
+    /// a timestamp, which arrive slightly out of order: while [Inserter]s keep
+    /// committing items, a consumer may repeatedly drain all items below a
+    /// watermark, i.e. a value below which no new items are expected.
+    ///
+    /// Items still held by [Inserter]s, e.g. in partially filled buckets, are
+    /// not affected. Consider [flushing](Inserter::flush) them regularly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// let mut inserter = sortbuf::Inserter::new(&mut sortbuf);
+    /// inserter.insert_items_reversed([3, 1, 4, 2, 6]).expect("Failed to insert items");
+    /// drop(inserter);
+    ///
+    /// assert!(sortbuf.drain_below(&4).expect("Failed to drain items").eq([1, 2, 3]));
+    ///
+    /// let mut inserter = sortbuf::Inserter::new(&mut sortbuf);
+    /// inserter.insert_items_reversed([5, 7]).expect("Failed to insert items");
+    /// drop(inserter);
+    ///
+    /// assert!(sortbuf.drain_below(&7).expect("Failed to drain items").eq([4, 5, 6]));
+    /// assert!(sortbuf.unreversed().eq([7]));
+    /// ```
+    ///
+    /// If an allocation fails, an error is returned and the buffer is left
+    /// unaltered.
+    ///
+    /// # Time complexity
+    ///
+    /// See [drain_from](Self::drain_from).
+    pub fn drain_below(&mut self, watermark: &T) -> Result<ext::Sorted<T, M>, error::InsertionError>
+    where M: Clone,
+    {
+        self.drain_upper(|core::cmp::Reverse(i)| i >= watermark).map(ext::Sorted)
     }
 }
 
-impl<T: Ord> IntoIterator for SortBuf<T> {
+impl<T: Ord, M: BucketAllocator<T>> IntoIterator for SortBuf<T, M> {
     type Item = T;
-    type IntoIter = iter::Iter<Self::Item>;
+    type IntoIter = iter::Iter<Self::Item, M>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.buckets.into()
+        let (buckets, allocator) = self.into_parts();
+        iter::Iter::new(buckets, allocator)
+    }
+}
+
+impl<T: Ord, M: BucketAllocator<T>> Drop for SortBuf<T, M> {
+    fn drop(&mut self) {
+        self.buckets.drain(..).for_each(|b| self.allocator.release(b.into_inner()))
     }
 }
 
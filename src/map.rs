@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: MIT
+//! [SortMap] and related utilities for sorting key-value pairs
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::iter::FusedIterator;
+
+use super::{iter, BucketAllocator, SortBuf};
+use super::bucket::Bucket;
+use super::error::{InsertionError, InsertionResult};
+use super::inserter::{BucketAccumulator, Inserter};
+
+
+/// A key-value pair ordered by its key only
+///
+/// This type is the item type of a [SortMap]. Its implementations of [Ord] and
+/// [PartialEq] only consider the key, ignoring the value entirely.
+#[derive(Copy, Clone, Default)]
+pub struct KeyValue<K, V> {
+    /// The key by which the pair is ordered
+    pub key: K,
+    /// The value associated with the key
+    pub value: V,
+}
+
+impl<K, V> KeyValue<K, V> {
+    /// Create a new key-value pair
+    pub fn new(key: K, value: V) -> Self {
+        Self{key, value}
+    }
+
+    /// Convert this key-value pair into a tuple
+    pub fn into_tuple(self) -> (K, V) {
+        (self.key, self.value)
+    }
+}
+
+impl<K, V> From<(K, V)> for KeyValue<K, V> {
+    fn from((key, value): (K, V)) -> Self {
+        Self::new(key, value)
+    }
+}
+
+impl<K: Ord, V> Ord for KeyValue<K, V> {
+    #[inline(always)]
+    fn cmp(&self, other: &Self) -> Ordering {
+        Ord::cmp(&self.key, &other.key)
+    }
+}
+
+impl<K: Ord, V> PartialOrd for KeyValue<K, V> {
+    #[inline(always)]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> Eq for KeyValue<K, V> {}
+
+impl<K: Ord, V> PartialEq for KeyValue<K, V> {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        PartialEq::eq(&self.key, &other.key)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for KeyValue<K, V> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("KeyValue").field(&self.key).field(&self.value).finish()
+    }
+}
+
+
+/// [Bucket] holding [KeyValue] pairs
+type PairBucket<K, V> = Bucket<KeyValue<K, V>>;
+
+impl<K: Ord, V> PairBucket<K, V> {
+    /// Combine values of pairs with equal keys
+    ///
+    /// This function replaces all pairs sharing a key with a single pair,
+    /// combining their values using `combiner`. The combined pairs are moved to
+    /// a new allocation sized to fit them, releasing the bucket's original
+    /// storage. If that allocation fails, the pairs are combined in place
+    /// instead, i.e. the bucket retains its storage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sortbuf::{Bucket, KeyValue};
+    ///
+    /// let pairs = [("a", 1), ("b", 2), ("a", 3)].map(KeyValue::from);
+    /// let bucket = Bucket::from_unsorted(pairs.into()).combine_values(|a, b| a + b);
+    /// assert!(bucket.into_vec().into_iter().map(KeyValue::into_tuple).eq([("a", 4), ("b", 2)]));
+    /// ```
+    ///
+    /// A [MapBuckets](super::adapters::MapBuckets) adapter allows combining
+    /// values before buckets reach a shared [SortMap]:
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// use std::sync::Mutex;
+    /// use sortbuf::Bucket;
+    ///
+    /// let map = Mutex::new(sortbuf::SortMap::with_combiner(|a, b| a + b));
+    /// let combining = sortbuf::adapters::MapBuckets::new(&map, |b: Bucket<_>| b.combine_values(|a, b| a + b));
+    /// let mut inserter = sortbuf::Inserter::new(combining);
+    /// inserter.insert_pairs([("b", 1), ("a", 2), ("b", 3)]).expect("Failed to insert items");
+    /// drop(inserter);
+    /// assert!(map.into_inner().unwrap().into_iter().eq([("b", 4), ("a", 2)]));
+    /// # }
+    /// ```
+    ///
+    /// # Time complexity
+    ///
+    /// Combining comes with a run-time cost of O(_b_) with bucket size _b_.
+    pub fn combine_values(self, mut combiner: impl FnMut(V, V) -> V) -> Self {
+        let items = self.into_vec();
+        let distinct = items.windows(2).filter(|w| w[0] != w[1]).count() + 1;
+        if distinct >= items.len() {
+            return Bucket::from_sorted_unchecked(items)
+        }
+
+        let mut combined = Vec::new();
+        if combined.try_reserve_exact(distinct).is_ok() {
+            let mut items = items.into_iter();
+            if let Some(mut current) = items.next() {
+                for item in items {
+                    if item == current {
+                        current.value = combiner(current.value, item.value)
+                    } else {
+                        combined.push(core::mem::replace(&mut current, item))
+                    }
+                }
+                combined.push(current)
+            }
+            return Bucket::from_sorted_unchecked(combined)
+        }
+
+        // Consumed items are popped from the front while combined items are
+        // pushed to the back, which never exceeds the original capacity.
+        let mut items = VecDeque::from(items);
+        if let Some(mut current) = items.pop_front() {
+            for _ in 0..items.len() {
+                let item = items.pop_front().expect("Unexpectedly ran out of items");
+                if item == current {
+                    current.value = combiner(current.value, item.value)
+                } else {
+                    items.push_back(core::mem::replace(&mut current, item))
+                }
+            }
+            items.push_back(current)
+        }
+
+        Bucket::from_sorted_unchecked(items.into())
+    }
+}
+
+
+/// Data structure for sorting key-value pairs by their keys
+///
+/// This data structure buffers [KeyValue] pairs for later iteration in
+/// descending order of their keys, much like a [SortBuf]. In fact, it is built
+/// on top of one and items are inserted via an [Inserter] in the same way.
+///
+/// A `SortMap` may optionally be constructed with a combiner via
+/// [with_combiner](Self::with_combiner). In that case, values of pairs with
+/// equal keys are combined using that function. Combining happens for each
+/// [Bucket] right after it is committed to the map, which pre-aggregates the
+/// values and thus shrinks the bucket, and again during iteration across
+/// buckets. The [Iterator] provided through this type's [IntoIterator] impl
+/// then yields each key only once. The combiner is expected to be associative
+/// and commutative, as the order in which values are combined is unspecified.
+///
+/// Combining a bucket never fails, but it takes time linear in the size of the
+/// bucket and usually involves moving the combined pairs to a smaller
+/// allocation. For a map shared between multiple [Inserter]s, e.g. via a
+/// `Mutex`, that work is done while holding the lock. Buckets may be combined
+/// beforehand via [Bucket::combine_values], e.g. using a
+/// [MapBuckets](super::adapters::MapBuckets) adapter. In that case, the map
+/// will only check whether there are any values left to combine.
+///
+/// # Examples
+///
+/// ```
+/// let mut map = sortbuf::SortMap::with_combiner(|a, b| a + b);
+/// let mut inserter = sortbuf::Inserter::new(&mut map);
+/// inserter.insert_pairs([("b", 1), ("a", 2), ("b", 3)]).expect("Failed to insert items");
+/// drop(inserter);
+/// assert!(map.into_iter().eq([("b", 4), ("a", 2)]));
+/// ```
+///
+/// # Other notes
+///
+/// The omission of an implementation of [Clone] for this type is on purpose, as
+/// it is meant for large amounts of data.
+pub struct SortMap<K: Ord, V, C = fn(V, V) -> V> {
+    buf: SortBuf<KeyValue<K, V>>,
+    combiner: Option<C>,
+}
+
+impl<K: Ord, V> SortMap<K, V> {
+    /// Create a new map which does not combine values
+    ///
+    /// Iterating over the map will yield all pairs inserted, including pairs
+    /// with equal keys.
+    pub fn new() -> Self {
+        Self{buf: Default::default(), combiner: None}
+    }
+}
+
+impl<K: Ord, V, C: FnMut(V, V) -> V> SortMap<K, V, C> {
+    /// Create a new map combining values of equal keys with `combiner`
+    pub fn with_combiner(combiner: C) -> Self {
+        Self{buf: Default::default(), combiner: Some(combiner)}
+    }
+
+    /// Take this map's contents, leaving an empty map with the same combiner
+    pub fn take(&mut self) -> Self where C: Clone {
+        Self{buf: self.buf.take(), combiner: self.combiner.clone()}
+    }
+
+}
+
+impl<K: Ord, V> Default for SortMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V, C: FnMut(V, V) -> V> BucketAccumulator for SortMap<K, V, C> {
+    type Item = KeyValue<K, V>;
+
+    fn add_bucket(&mut self, bucket: Bucket<Self::Item>) -> InsertionResult<Bucket<Self::Item>> {
+        let bucket = match self.combiner.as_mut() {
+            Some(combiner) => bucket.combine_values(combiner),
+            None => bucket,
+        };
+        self.buf.add_bucket(bucket)
+    }
+
+    fn reserve_buckets(&mut self, additional: usize) -> Result<(), InsertionError> {
+        self.buf.reserve_buckets(additional)
+    }
+}
+
+impl<K: Ord, V, C: FnMut(V, V) -> V> IntoIterator for SortMap<K, V, C> {
+    type Item = (K, V);
+    type IntoIter = Iter<K, V, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Iter{inner: self.buf.into_iter(), combiner: self.combiner, peeked: None}
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug, C> fmt::Debug for SortMap<K, V, C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SortMap")
+            .field("buf", &self.buf)
+            .field("combining", &self.combiner.is_some())
+            .finish()
+    }
+}
+
+
+/// [Iterator] yielding key-value pairs in descending order of their keys
+///
+/// If the originating [SortMap] was constructed with a combiner, this iterator
+/// combines values of consecutive pairs with equal keys and yields each key only
+/// once.
+pub struct Iter<K: Ord, V, C> {
+    inner: iter::Iter<KeyValue<K, V>>,
+    combiner: Option<C>,
+    peeked: Option<KeyValue<K, V>>,
+}
+
+impl<K: Ord, V, C: FnMut(V, V) -> V> FusedIterator for Iter<K, V, C> {}
+
+impl<K: Ord, V, C: FnMut(V, V) -> V> Iterator for Iter<K, V, C> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut current = self.peeked.take().or_else(|| self.inner.next())?;
+        if let Some(combiner) = self.combiner.as_mut() {
+            for item in self.inner.by_ref() {
+                if item == current {
+                    current.value = combiner(current.value, item.value)
+                } else {
+                    self.peeked = Some(item);
+                    break
+                }
+            }
+        }
+        Some(current.into_tuple())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        let peeked = self.peeked.is_some() as usize;
+        let lower = if self.combiner.is_some() {
+            core::cmp::min(lower + peeked, 1)
+        } else {
+            lower + peeked
+        };
+        (lower, upper.map(|u| u + peeked))
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug, C> fmt::Debug for Iter<K, V, C> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Iter")
+            .field("inner", &self.inner)
+            .field("combining", &self.combiner.is_some())
+            .finish()
+    }
+}
+
+
+impl<A, M, K, V> Inserter<A, M>
+where
+    A: BucketAccumulator<Item = KeyValue<K, V>>,
+    M: BucketAllocator<A::Item>,
+    K: Ord,
+{
+    /// Insert key-value pairs into the accumulator
+    ///
+    /// This function inserts the given `items` to the buffer, each converted to
+    /// a [KeyValue]. If the insertion fails due to an (re-)allocation failure,
+    /// an error is returned.
+    pub fn insert_pairs(
+        &mut self,
+        items: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), InsertionError> {
+        self.insert_items(items.into_iter().map(Into::into))
+    }
+}
+
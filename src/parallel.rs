@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: MIT
+//! Utilities for processing buffered items on multiple threads
+
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::{BucketAccumulator, BucketAllocator, Global, Inserter, SortBuf, DEFAULT_BUCKET_BYTESIZE};
+use super::error::InsertionError;
+
+
+impl<T: Ord + Send> SortBuf<T> {
+    /// Insert items from multiple threads
+    ///
+    /// This function spawns `threads` scoped threads, each calling `f` with a
+    /// dedicated [Inserter] for this buffer and the index of the thread. Once
+    /// all threads finished and their [Inserter]s committed all their items,
+    /// the filled buffer is returned. Since the threads are scoped, `f` may
+    /// borrow from the calling function's environment.
+    ///
+    /// If `f` returns an error on any of the threads, the first such error is
+    /// returned alongside the buffer holding all items inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::num::NonZeroUsize;
+    /// let sortbuf = sortbuf::SortBuf::new().fill_parallel(
+    ///     NonZeroUsize::new(4).unwrap(),
+    ///     |inserter, n| inserter.insert_items((0..1000).map(|i| 4*i + n)),
+    /// ).expect("Failed to insert items");
+    /// assert!(sortbuf.into_iter().eq((0..4000).rev()));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics on one of the threads, the panic is propagated to the
+    /// calling thread once all other threads finished.
+    pub fn fill_parallel<F>(self, threads: NonZeroUsize, f: F) -> Result<Self, (InsertionError, Self)>
+    where F: Fn(&mut Inserter<&Mutex<Self>>, usize) -> Result<(), InsertionError> + Sync,
+    {
+        let buf = Mutex::new(self);
+
+        let res = thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads.get()).map(|n| {
+                let (buf, f) = (&buf, &f);
+                scope.spawn(move || f(&mut Inserter::new(buf), n))
+            }).collect();
+
+            join_all(handles, Ok(()))
+        });
+
+        let buf = buf.into_inner().expect("Could not lock mutex!");
+        match res {
+            Ok(()) => Ok(buf),
+            Err(e) => Err((e, buf)),
+        }
+    }
+
+    /// Create a buffer from the items of an [Iterator] using worker threads
+    ///
+    /// This function collects the items yielded by `iter` into plain [Vec]s of
+    /// a [default bucket size](DEFAULT_BUCKET_BYTESIZE) on the calling thread
+    /// and hands them to `workers` worker threads. These sort the [Vec]s and
+    /// commit them as [Bucket](super::Bucket)s. Thus, the sorting involved in
+    /// the insertion is parallelized even if all items originate from a single
+    /// source. The number of [Vec]s in flight is limited to twice the number
+    /// of `workers`. The last [Vec] is shrunk to fit its items.
+    ///
+    /// If an allocation fails, no further items are pulled from `iter` and the
+    /// first error is returned alongside a buffer holding the items inserted
+    /// successfully and the [Vec]s of items which were collected but could not
+    /// be committed. Items not yet pulled remain in `iter`, which may be
+    /// passed [by reference](Iterator::by_ref) for retrieving them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::num::NonZeroUsize;
+    /// let items = (0..10_000).map(|i| (i * 7919) % 10_000);
+    /// let sortbuf = sortbuf::SortBuf::from_iter_parallel(items, NonZeroUsize::new(4).unwrap())
+    ///     .expect("Failed to insert items");
+    /// assert!(sortbuf.into_iter().eq((0..10_000).rev()));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If a worker thread panics, the panic is propagated to the calling thread
+    /// once all other threads finished.
+    pub fn from_iter_parallel(
+        iter: impl IntoIterator<Item = T>,
+        workers: NonZeroUsize,
+    ) -> Result<Self, (InsertionError, Self, Vec<Vec<T>>)> {
+        let buf = Mutex::new(Self::new());
+        let res = fill_from_iter(&buf, iter, workers);
+        let buf = buf.into_inner().expect("Could not lock mutex!");
+        match res {
+            Ok(()) => Ok(buf),
+            Err((e, rejected)) => Err((e, buf, rejected)),
+        }
+    }
+
+    /// Process all items in chunks on multiple threads, preserving their order
+    ///
+    /// This function drains this buffer in chunks of `chunk_size` items in
+    /// descending order. Each chunk is passed to `f` on one of `threads` worker
+    /// threads. The results are passed to `sink` on the calling thread strictly
+    /// in the order of the chunks they were computed from. Thus, expensive
+    /// per-item work such as formatting or serialization can be spread over
+    /// multiple threads while the calling thread merges the buckets and, e.g.,
+    /// writes the results.
+    ///
+    /// The number of chunks in flight, i.e. chunks pulled from the buffer but
+    /// whose results were not yet passed to `sink`, is limited to twice the
+    /// number of `threads`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::num::NonZeroUsize;
+    /// let mut sortbuf = sortbuf::SortBuf::new();
+    /// sortbuf::Inserter::new(&mut sortbuf).insert_items(0..1000).expect("Failed to insert items");
+    ///
+    /// let mut lines = String::new();
+    /// sortbuf.for_each_chunk_ordered(
+    ///     NonZeroUsize::new(4).unwrap(),
+    ///     NonZeroUsize::new(64).unwrap(),
+    ///     |chunk| chunk.into_iter().map(|i| format!("{i}\n")).collect::<String>(),
+    ///     |formatted| lines.push_str(&formatted),
+    /// );
+    /// assert!(lines.lines().map(|l| l.parse::<i32>().unwrap()).eq((0..1000).rev()));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `f` panics on one of the worker threads, no further chunks are
+    /// dispatched or passed to `sink` and the panic is propagated to the
+    /// calling thread once all other threads finished.
+    pub fn for_each_chunk_ordered<R, F, S>(
+        self,
+        threads: NonZeroUsize,
+        chunk_size: NonZeroUsize,
+        f: F,
+        mut sink: S,
+    ) where R: Send,
+            F: Fn(Vec<T>) -> R + Sync,
+            S: FnMut(R),
+    {
+        let mut items = self.into_iter();
+        let max_in_flight = threads.get().saturating_mul(2);
+
+        thread::scope(|scope| {
+            let (job_sender, jobs) = mpsc::sync_channel(threads.get());
+            let (result_sender, results) = mpsc::channel();
+            let jobs = Arc::new(Mutex::new(jobs));
+
+            for _ in 0..threads.get() {
+                let jobs = jobs.clone();
+                let results = result_sender.clone();
+                let f = &f;
+                scope.spawn(move || {
+                    while let Some((index, chunk)) = next_job(&jobs) {
+                        // A panic is passed on rather than unwinding the
+                        // worker, which would leave the calling thread waiting
+                        // for results while other workers wait for jobs.
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| f(chunk)));
+                        let failed = result.is_err();
+                        if results.send((index, result)).is_err() || failed {
+                            break
+                        }
+                    }
+                });
+            }
+            drop(result_sender);
+
+            let mut reorder = Reorder::new();
+            let mut dispatched = 0;
+            let res = loop {
+                if dispatched - reorder.delivered >= max_in_flight {
+                    match results.recv() {
+                        Ok((index, Ok(result))) => reorder.insert(index, result, &mut sink),
+                        Ok((_, Err(payload))) => break Err(payload),
+                        Err(_) => break Ok(()),
+                    }
+                    continue
+                }
+
+                let chunk: Vec<_> = items.by_ref().take(chunk_size.get()).collect();
+                if chunk.is_empty() || job_sender.send((dispatched, chunk)).is_err() {
+                    break Ok(())
+                }
+                dispatched += 1;
+            };
+
+            drop(job_sender);
+            res.and_then(|_| results.iter().try_for_each(|(index, result)| {
+                reorder.insert(index, result?, &mut sink);
+                Ok(())
+            }))
+        }).unwrap_or_else(|p| panic::resume_unwind(p))
+    }
+}
+
+
+/// Insert the items of an [Iterator] into an accumulator using worker threads
+///
+/// This function implements [SortBuf::from_iter_parallel] for an arbitrary
+/// accumulator. If an allocation fails, the first error is returned alongside
+/// the [Vec]s of items which were collected but could not be committed.
+pub(crate) fn fill_from_iter<A, T>(
+    acc: &Mutex<A>,
+    iter: impl IntoIterator<Item = T>,
+    workers: NonZeroUsize,
+) -> Result<(), (InsertionError, Vec<Vec<T>>)>
+where A: BucketAccumulator<Item = T> + Send,
+      T: Ord + Send,
+{
+    let bucket_size = core::cmp::max(DEFAULT_BUCKET_BYTESIZE / core::cmp::max(core::mem::size_of::<T>(), 1), 1);
+    // After a failure, each worker rejects at most one chunk in progress
+    // and the calling thread at most one chunk, besides chunks queued.
+    let max_rejected = workers.get().saturating_mul(2).saturating_add(1);
+    let mut items = iter.into_iter();
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (sender, chunks) = mpsc::sync_channel(workers.get());
+        let chunks = Arc::new(Mutex::new(chunks));
+
+        let handles: Vec<_> = (0..workers.get()).map(|_| {
+            let (chunks, failed) = (chunks.clone(), &failed);
+            scope.spawn(move || {
+                let mut rejected = Vec::new();
+                if let Err(e) = rejected.try_reserve_exact(max_rejected) {
+                    failed.store(true, Ordering::Release);
+                    return (Err(e.into()), rejected)
+                }
+
+                // Chunks received after a failure are rejected as well,
+                // which also drains the channel once it is closed.
+                let mut inserter = Inserter::new(acc);
+                let mut res = Ok(());
+                while let Some(chunk) = next_job(&chunks) {
+                    if res.is_err() {
+                        rejected.push(chunk)
+                    } else if let Err((e, chunk)) = inserter.insert_vec(chunk) {
+                        failed.store(true, Ordering::Release);
+                        rejected.push(chunk);
+                        res = Err(e);
+                    }
+                }
+                (res, rejected)
+            })
+        }).collect();
+
+        // Once all workers quit, sending will fail rather than block.
+        drop(chunks);
+
+        let mut rejected = Vec::new();
+        let res = rejected.try_reserve_exact(max_rejected).map_err(Into::into).and_then(|_| loop {
+            if failed.load(Ordering::Acquire) {
+                break Ok(())
+            }
+            let mut chunk = Global.allocate(bucket_size)?;
+            chunk.extend(items.by_ref().take(bucket_size));
+            if chunk.len() < bucket_size {
+                Global.shrink(&mut chunk, 0)
+            }
+            if chunk.is_empty() {
+                break Ok(())
+            }
+            if let Err(mpsc::SendError(chunk)) = sender.send(chunk) {
+                rejected.push(chunk);
+                break Ok(())
+            }
+        });
+        drop(sender);
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|p| panic::resume_unwind(p)))
+            .fold(res, |res, (worker_res, worker_rejected)| {
+                rejected.extend(worker_rejected);
+                res.and(worker_res)
+            })
+            .map_err(|e| (e, rejected))
+    })
+}
+
+
+/// Join all threads, returning the first error
+///
+/// Panics of any of the threads are propagated.
+fn join_all(
+    handles: Vec<thread::ScopedJoinHandle<'_, Result<(), InsertionError>>>,
+    init: Result<(), InsertionError>,
+) -> Result<(), InsertionError> {
+    handles
+        .into_iter()
+        .map(|h| h.join().unwrap_or_else(|p| std::panic::resume_unwind(p)))
+        .fold(init, Result::and)
+}
+
+
+/// Retrieve the next job from a shared [mpsc::Receiver]
+fn next_job<J>(jobs: &Mutex<mpsc::Receiver<J>>) -> Option<J> {
+    jobs.lock().ok()?.recv().ok()
+}
+
+
+/// Reordering buffer for results of chunks processed out of order
+struct Reorder<R> {
+    pending: BTreeMap<usize, R>,
+    delivered: usize,
+}
+
+impl<R> Reorder<R> {
+    /// Create a new, empty reordering buffer
+    fn new() -> Self {
+        Self{pending: Default::default(), delivered: 0}
+    }
+
+    /// Insert a result, passing all results now in order to the `sink`
+    fn insert(&mut self, index: usize, result: R, sink: &mut impl FnMut(R)) {
+        self.pending.insert(index, result);
+        while let Some(result) = self.pending.remove(&self.delivered) {
+            sink(result);
+            self.delivered += 1;
+        }
+    }
+}
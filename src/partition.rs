@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: MIT
+//! Sample sort partitioning of items over multiple accumulators
+
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+
+use super::error::InsertionError;
+use super::inserter::{BucketAccumulator, Inserter};
+
+
+/// Item feeder distributing items over multiple [BucketAccumulator]s
+///
+/// Instances of this type route each item to one of a number of partitions,
+/// each feeding its own [BucketAccumulator] via an [Inserter]. Each partition
+/// covers a range of items, with the ranges being disjoint. Partitions are
+/// ordered: all items in the first partition are less than or equal to all
+/// items in the second partition and so on. Thus, once all items are inserted,
+/// the accumulators may be drained independently, e.g. concurrently, and their
+/// results concatenated (in reverse order of the partitions for descending
+/// order).
+///
+/// The boundaries between partitions, or splitters, are chosen from a sample of
+/// the input: the first items inserted are retained until the sample is
+/// complete. From that sample, splitters are chosen such that the partitions
+/// will be roughly equal in size, assuming the sample is representative for
+/// the entire input. Alternatively, splitters may be supplied via
+/// [with_splitters](Self::with_splitters).
+///
+/// Multiple `PartitionedInserter`s may feed the same accumulators, e.g. shared
+/// via `Mutex`es, only if they all use the same splitters. Otherwise, their
+/// partitions will overlap. In that case, create one `PartitionedInserter`
+/// via [new](Self::new) and, once its [splitters](Self::splitters) are chosen,
+/// all others via [with_splitters](Self::with_splitters).
+///
+/// # Examples
+///
+/// ```
+/// # use std::num::NonZeroUsize;
+/// let mut bufs: Vec<sortbuf::SortBuf<_>> = (0..4).map(|_| Default::default()).collect();
+/// let mut inserter = sortbuf::PartitionedInserter::new(bufs.iter_mut(), NonZeroUsize::new(64).unwrap());
+/// inserter.insert_items((0..1000).map(|i| (i * 7919) % 1000)).expect("Failed to insert items");
+/// drop(inserter);
+///
+/// assert!(bufs.into_iter().rev().flatten().eq((0..1000).rev()));
+/// ```
+///
+/// # Time complexity
+///
+/// Routing a single item to its partition has a runtime cost of O(log(_p_))
+/// with _p_ denoting the number of partitions. Choosing the splitters requires
+/// sorting the sample.
+#[derive(Debug)]
+pub struct PartitionedInserter<A: BucketAccumulator> where A::Item: Clone {
+    inserters: Vec<Inserter<A>>,
+    splitters: Option<Vec<A::Item>>,
+    sample: Vec<A::Item>,
+    sample_size: NonZeroUsize,
+}
+
+impl<A: BucketAccumulator> PartitionedInserter<A> where A::Item: Clone {
+    /// Create a new `PartitionedInserter`
+    ///
+    /// Create a new `PartitionedInserter` for the given `bucket_accumulators`,
+    /// with one partition per accumulator. Splitters will be chosen based on
+    /// the first `sample_size` items inserted.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if no accumulators are supplied.
+    pub fn new(bucket_accumulators: impl IntoIterator<Item = A>, sample_size: NonZeroUsize) -> Self {
+        let inserters: Vec<_> = bucket_accumulators.into_iter().map(Inserter::new).collect();
+        assert!(!inserters.is_empty(), "No accumulators supplied");
+        Self{inserters, splitters: None, sample: Default::default(), sample_size}
+    }
+
+    /// Create a new `PartitionedInserter` using the given splitters
+    ///
+    /// Create a new `PartitionedInserter` for the given `bucket_accumulators`,
+    /// with one partition per accumulator. Rather than choosing splitters from
+    /// a sample, the given `splitters` are used. The _i_-th splitter is the
+    /// least item routed to the (_i_+1)-th partition. This allows multiple
+    /// `PartitionedInserter`s to feed the same accumulators.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// # use std::num::NonZeroUsize;
+    /// use std::sync::Mutex;
+    ///
+    /// let bufs: Vec<Mutex<sortbuf::SortBuf<_>>> = (0..4).map(|_| Default::default()).collect();
+    /// let mut first = sortbuf::PartitionedInserter::new(bufs.iter(), NonZeroUsize::new(64).unwrap());
+    /// first.insert_items((0..500).map(|i| (i * 7919) % 1000)).expect("Failed to insert items");
+    ///
+    /// let splitters = first.splitters().expect("No splitters chosen").to_vec();
+    /// let mut second = sortbuf::PartitionedInserter::with_splitters(bufs.iter(), splitters);
+    /// second.insert_items((500..1000).map(|i| (i * 7919) % 1000)).expect("Failed to insert items");
+    /// drop((first, second));
+    ///
+    /// let bufs = bufs.into_iter().rev().map(|b| b.into_inner().unwrap());
+    /// assert!(bufs.flatten().eq((0..1000).rev()));
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This function panics if no accumulators are supplied, if the number of
+    /// `splitters` is not one less than the number of accumulators or if the
+    /// `splitters` are not in ascending order.
+    pub fn with_splitters(bucket_accumulators: impl IntoIterator<Item = A>, splitters: Vec<A::Item>) -> Self {
+        let inserters: Vec<_> = bucket_accumulators.into_iter().map(Inserter::new).collect();
+        assert!(!inserters.is_empty(), "No accumulators supplied");
+        assert_eq!(splitters.len(), inserters.len() - 1, "Number of splitters doesn't match partitions");
+        assert!(splitters.windows(2).all(|w| w[0] <= w[1]), "Splitters not in ascending order");
+        let sample_size = NonZeroUsize::MIN;
+        Self{inserters, splitters: Some(splitters), sample: Default::default(), sample_size}
+    }
+
+    /// Insert items into the accumulators
+    ///
+    /// This function inserts the given `items` into the partitions' buffers. If
+    /// the insertion fails due to an (re-)allocation failure, an error is
+    /// returned.
+    ///
+    /// Even in the event of such an error, all items consumed from the
+    /// `Iterator` passed to this method will reside either in one of the
+    /// underlying [BucketAccumulator]s or in internal accumulators after the
+    /// operation. Thus, callers can recover from allocation failures without
+    /// any data loss by passing a mutable reference to an [Iterator] rather
+    /// than a value, e.g. the result of [Iterator::by_ref].
+    pub fn insert_items(&mut self, items: impl IntoIterator<Item = A::Item>) -> Result<(), InsertionError> {
+        let mut items = items.into_iter().fuse();
+
+        if self.splitters.is_none() {
+            let missing = self.sample_size.get() - self.sample.len();
+            self.sample.try_reserve(missing)?;
+            self.sample.extend(items.by_ref().take(missing));
+            if self.sample.len() < self.sample_size.get() {
+                return Ok(())
+            }
+            self.choose_splitters()?;
+        }
+        self.route_sample()?;
+
+        // Make sure we can put back items on failure without allocating.
+        self.sample.try_reserve(2)?;
+        let splitters = self.splitters.as_deref().unwrap_or_default();
+        if let [inserter] = self.inserters.as_mut_slice() {
+            return inserter.insert_items(items)
+        }
+
+        // We route items in chunks of consecutive items belonging to the same
+        // partition in order to amortize the overhead of inserting them.
+        let mut next = items.next();
+        while let Some(item) = next.take() {
+            let partition = partition_of(splitters, &item);
+            let mut first = Some(item);
+            let chunk = core::iter::from_fn(|| first.take().or_else(|| {
+                let item = items.next()?;
+                if partition_of(splitters, &item) == partition {
+                    Some(item)
+                } else {
+                    next = Some(item);
+                    None
+                }
+            }));
+
+            if let Err(e) = self.inserters[partition].insert_items(chunk) {
+                // The inserter may have failed before consuming the chunk's
+                // first item, and the item we peeked at needs to go somewhere
+                // as well. The sample's storage is as good a place as any.
+                self.sample.extend(first);
+                self.sample.extend(next);
+                return Err(e)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the number of partitions
+    pub fn partitions(&self) -> usize {
+        self.inserters.len()
+    }
+
+    /// Retrieve the splitters between partitions
+    ///
+    /// This function returns the splitters chosen, in ascending order, or
+    /// `None` if the sample is not yet complete. The _i_-th splitter is the
+    /// least item routed to the (_i_+1)-th partition.
+    pub fn splitters(&self) -> Option<&[A::Item]> {
+        self.splitters.as_deref()
+    }
+
+    /// Set a new target bucket size for all partitions
+    ///
+    /// After calling this function, all inserters will commit [Bucket]s
+    /// containing near `size` items.
+    ///
+    /// [Bucket]: super::Bucket
+    pub fn set_bucket_size(&mut self, size: NonZeroUsize) -> &mut Self {
+        self.inserters.iter_mut().for_each(|i| { i.set_bucket_size(size); });
+        self
+    }
+
+    /// Set a new target bucket size in bytes for all partitions
+    ///
+    /// After calling this function, all inserters will commit [Bucket]s near
+    /// `bytesize` bytes in size.
+    ///
+    /// [Bucket]: super::Bucket
+    pub fn set_bucket_bytesize(&mut self, bytesize: usize) -> &mut Self {
+        self.inserters.iter_mut().for_each(|i| { i.set_bucket_bytesize(bytesize); });
+        self
+    }
+
+    /// Choose splitters from the sample
+    ///
+    /// The splitters are chosen such that the sample is split into partitions
+    /// of equal size. The sample is left sorted.
+    fn choose_splitters(&mut self) -> Result<(), InsertionError> {
+        let partitions = self.inserters.len();
+        let mut splitters = Vec::new();
+        splitters.try_reserve_exact(partitions - 1)?;
+
+        self.sample.sort_unstable();
+        let len = self.sample.len();
+        if len > 0 {
+            splitters.extend((1..partitions).map(|p| self.sample[p * len / partitions].clone()));
+        }
+        self.splitters = Some(splitters);
+        Ok(())
+    }
+
+    /// Route the items retained in the sample to their partitions
+    fn route_sample(&mut self) -> Result<(), InsertionError> {
+        let splitters = self.splitters.as_deref().unwrap_or_default();
+        self.sample.sort_unstable();
+
+        while let Some(last) = self.sample.last() {
+            let partition = partition_of(splitters, last);
+            let start = match partition.checked_sub(1) {
+                Some(p) => self.sample.partition_point(|i| *i < splitters[p]),
+                None    => 0,
+            };
+
+            // Items not consumed by the inserter remain in the sample.
+            let sample = &mut self.sample;
+            let items = core::iter::from_fn(|| if sample.len() > start { sample.pop() } else { None });
+            self.inserters[partition].insert_items(items)?;
+        }
+        Ok(())
+    }
+}
+
+impl<A: BucketAccumulator> Extend<A::Item> for PartitionedInserter<A> where A::Item: Clone {
+    fn extend<I: IntoIterator<Item = A::Item>>(&mut self, iter: I) {
+        self.insert_items(iter).expect("Failed to insert items")
+    }
+}
+
+impl<A: BucketAccumulator> Drop for PartitionedInserter<A> where A::Item: Clone {
+    fn drop(&mut self) {
+        if self.splitters.is_none() {
+            self.choose_splitters().expect("Failed to choose splitters")
+        }
+        self.route_sample().expect("Failed to route sampled items")
+    }
+}
+
+
+/// Determine the partition for an item given a list of splitters
+fn partition_of<T: Ord>(splitters: &[T], item: &T) -> usize {
+    splitters.partition_point(|s| s <= item)
+}
@@ -0,0 +1,636 @@
+// SPDX-License-Identifier: MIT
+//! [RecordBuf] and related utilities for sorting variable-length byte records
+
+use alloc::collections::binary_heap::{self, BinaryHeap};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::bucket::DEFAULT_BUCKET_BYTESIZE;
+use super::error::{InsertionError, InsertionResult};
+
+
+/// Accumulator for [RecordBucket]s
+///
+/// This trait is the counterpart of [BucketAccumulator](super::BucketAccumulator)
+/// for [RecordBucket]s. Users will usually not implement this trait but rely on
+/// implementations provided by this library, such as [RecordBuf].
+pub trait RecordAccumulator {
+    /// Add a [RecordBucket] to this accumulator
+    ///
+    /// If the [RecordBucket] could not be added, an error is returned alongside
+    /// the [RecordBucket].
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket>;
+}
+
+impl<A: RecordAccumulator> RecordAccumulator for &mut A {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        A::add_record_bucket(self, bucket)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RecordAccumulator> RecordAccumulator for Mutex<A> {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        self.get_mut().expect("Could not lock mutex!").add_record_bucket(bucket)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RecordAccumulator> RecordAccumulator for &Mutex<A> {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        self.lock().expect("Could not lock mutex!").add_record_bucket(bucket)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RecordAccumulator> RecordAccumulator for Arc<Mutex<A>> {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        self.lock().expect("Could not lock mutex!").add_record_bucket(bucket)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RecordAccumulator> RecordAccumulator for RwLock<A> {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        self.get_mut().expect("Could not lock mutex!").add_record_bucket(bucket)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RecordAccumulator> RecordAccumulator for Arc<RwLock<A>> {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        self.write().expect("Could not lock mutex!").add_record_bucket(bucket)
+    }
+}
+
+
+/// Data structure for sorting a large number of variable-length byte records
+///
+/// This data structure is the counterpart of [SortBuf](super::SortBuf) for
+/// byte records such as lines of text. Rather than holding each record in a
+/// dedicated allocation, e.g. as a [String](alloc::string::String), records are
+/// stored contiguously in [RecordBucket]s. Each [RecordBucket] holds an index
+/// which includes a fixed-size prefix of each record. Thus, most comparisons
+/// do not involve accessing the records themselves, and the memory used is
+/// accounted for accurately.
+///
+/// Records are added via [RecordInserter]s and retrieved via
+/// [iter](Self::iter) or [iter_desc](Self::iter_desc). Alternatively, the
+/// buffer may be consumed via [into_iter](IntoIterator::into_iter) or
+/// [into_iter_desc](Self::into_iter_desc), releasing the memory of each
+/// [RecordBucket] once all of its records were yielded. Records are ordered
+/// lexicographically, i.e. like [slice]s of [u8].
+///
+/// # Examples
+///
+/// ```
+/// let mut records = sortbuf::RecordBuf::new();
+/// let mut inserter = sortbuf::RecordInserter::new(&mut records);
+/// inserter.insert_records(["pear", "apple", "fig"]).expect("Failed to insert records");
+/// drop(inserter);
+///
+/// assert!(records.iter().eq([b"apple".as_slice(), b"fig", b"pear"]));
+/// assert!(records.iter_desc().eq([b"pear".as_slice(), b"fig", b"apple"]));
+/// ```
+///
+/// # Time complexity
+///
+/// With _n_ denoting the number of records and _b_ denoting the number of
+/// records per bucket, inserting records comes with an estimated runtime cost
+/// of O(_n_ log(_b_)) and iterating over all records with a cost of
+/// O(_n_ log(_n_/_b_)), not accounting for the length of records.
+///
+/// # Other notes
+///
+/// The omission of an implementation of [Clone] for this type is on purpose, as
+/// it is meant for large amounts of data.
+#[derive(Debug, Default)]
+pub struct RecordBuf {
+    buckets: Vec<RecordBucket>,
+}
+
+impl RecordBuf {
+    /// Create a new record buffer
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Take this buffer's contents, leaving an empty buffer
+    pub fn take(&mut self) -> Self {
+        core::mem::take(self)
+    }
+
+    /// Retrieve the number of records in this buffer
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(RecordBucket::len).sum()
+    }
+
+    /// Check whether this buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(RecordBucket::is_empty)
+    }
+
+    /// Retrieve the number of bytes allocated for the records in this buffer
+    ///
+    /// The number includes the memory allocated for the records' index.
+    pub fn bytesize(&self) -> usize {
+        self.buckets.iter().map(RecordBucket::bytesize).sum()
+    }
+
+    /// Retrieve an [Iterator] over all records in ascending order
+    pub fn iter(&self) -> Records<'_> {
+        Records::new(&self.buckets, false)
+    }
+
+    /// Retrieve an [Iterator] over all records in descending order
+    pub fn iter_desc(&self) -> Records<'_> {
+        Records::new(&self.buckets, true)
+    }
+
+    /// Retrieve an [Iterator] over all records in descending order, consuming the buffer
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut records = sortbuf::RecordBuf::new();
+    /// let mut inserter = sortbuf::RecordInserter::new(&mut records);
+    /// inserter.insert_records(["pear", "apple", "fig"]).expect("Failed to insert records");
+    /// drop(inserter);
+    ///
+    /// assert!(records.into_iter_desc().eq([b"pear".to_vec(), b"fig".to_vec(), b"apple".to_vec()]));
+    /// ```
+    pub fn into_iter_desc(self) -> IntoRecords {
+        IntoRecords::new(self.buckets, true)
+    }
+}
+
+impl IntoIterator for RecordBuf {
+    type Item = Vec<u8>;
+    type IntoIter = IntoRecords;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoRecords::new(self.buckets, false)
+    }
+}
+
+impl RecordAccumulator for RecordBuf {
+    fn add_record_bucket(&mut self, bucket: RecordBucket) -> InsertionResult<RecordBucket> {
+        if bucket.is_empty() {
+            return Ok(())
+        }
+
+        match self.buckets.try_reserve(1) {
+            Ok(_)   => {
+                self.buckets.push(bucket);
+                Ok(())
+            },
+            Err(e)  => Err((e.into(), bucket)),
+        }
+    }
+}
+
+
+/// A sorted collection of variable-length byte records
+///
+/// This type holds the bytes of all records in a single, contiguous arena,
+/// alongside an index. Each entry of the index refers to one record and holds
+/// a prefix of the record. The index is sorted, i.e. records are ordered
+/// lexicographically.
+///
+/// Instances are usually created by [RecordInserter]s.
+///
+/// # Other notes
+///
+/// The omission of an implementation of [Clone] for this type is on purpose, as
+/// it is meant for large amounts of data.
+#[derive(Default)]
+pub struct RecordBucket {
+    data: Vec<u8>,
+    index: Vec<Entry>,
+}
+
+impl RecordBucket {
+    /// Create a new bucket from the given records
+    ///
+    /// If an allocation fails, an error is returned.
+    pub fn from_records<R: AsRef<[u8]>>(records: impl IntoIterator<Item = R>) -> Result<Self, InsertionError> {
+        let mut bucket: Self = Default::default();
+        records.into_iter().try_for_each(|r| bucket.push(r.as_ref(), usize::MAX).map(drop))?;
+        bucket.sort();
+        Ok(bucket)
+    }
+
+    /// Retrieve the number of records in this bucket
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check whether this bucket is empty
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Retrieve the number of bytes allocated for this bucket
+    pub fn bytesize(&self) -> usize {
+        self.data.capacity() + self.index.capacity() * core::mem::size_of::<Entry>()
+    }
+
+    /// Retrieve an [Iterator] over the records in this bucket in ascending order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &[u8]> + ExactSizeIterator {
+        self.index.iter().map(|e| self.get(e))
+    }
+
+    /// Append a record without sorting, keeping the bucket within `limit`
+    ///
+    /// The storage for records and the index grows by doubling, but not beyond
+    /// `limit` bytes in total, as reported by [bytesize](Self::bytesize). If
+    /// the record does not fit, `false` is returned and the record is not
+    /// appended. A record is always appended to an empty bucket.
+    ///
+    /// Neither the records nor the index may grow into the share of `limit`
+    /// the other will need, judging by the records appended so far. Otherwise,
+    /// one could take up all remaining space while the other is still short of
+    /// room for the next record.
+    fn push(&mut self, record: &[u8], limit: usize) -> Result<bool, InsertionError> {
+        let entry_size = core::mem::size_of::<Entry>();
+        let data_len = self.data.len().saturating_add(record.len());
+        let index_len = (self.index.len() + 1).saturating_mul(entry_size);
+        let data_bytes = core::cmp::max(data_len, self.data.capacity());
+        let index_bytes = core::cmp::max(index_len, self.index.capacity().saturating_mul(entry_size));
+        if data_bytes.saturating_add(index_bytes) > limit && !self.is_empty() {
+            return Ok(false)
+        }
+
+        let index_share = share(limit, index_len, data_len);
+        grow(&mut self.data, record.len(), limit.saturating_sub(core::cmp::max(index_bytes, index_share)))?;
+        let data_share = core::cmp::max(self.data.capacity(), share(limit, data_len, index_len));
+        grow(&mut self.index, 1, limit.saturating_sub(data_share) / entry_size)?;
+
+        self.index.push(Entry::new(record, self.data.len()));
+        self.data.extend_from_slice(record);
+        Ok(true)
+    }
+
+    /// Sort the index
+    fn sort(&mut self) {
+        let data = self.data.as_slice();
+        self.index.sort_unstable_by(|a, b| a.compare(b, data, data))
+    }
+
+    /// Retrieve the record referred to by an index entry
+    fn get(&self, entry: &Entry) -> &[u8] {
+        entry.record(&self.data)
+    }
+}
+
+impl fmt::Debug for RecordBucket {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "RecordBucket({} records)", self.len())
+    }
+}
+
+
+/// Entry in the index of a [RecordBucket]
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    /// The first bytes of the record, padded with zeroes, as big-endian number
+    prefix: u64,
+    offset: usize,
+    len: usize,
+}
+
+impl Entry {
+    /// Create a new entry for a record stored at the given `offset`
+    fn new(record: &[u8], offset: usize) -> Self {
+        let mut prefix = [0u8; 8];
+        let len = core::cmp::min(record.len(), prefix.len());
+        prefix[..len].copy_from_slice(&record[..len]);
+        Self{prefix: u64::from_be_bytes(prefix), offset, len: record.len()}
+    }
+
+    /// Retrieve the record this entry refers to from the given arena
+    fn record<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..self.offset + self.len]
+    }
+
+    /// Compare the records referred to by two entries
+    ///
+    /// Since zero is the least byte value, the order of the prefixes agrees
+    /// with the order of the records. Only records with equal prefixes are
+    /// compared in full.
+    fn compare(&self, other: &Self, data: &[u8], other_data: &[u8]) -> Ordering {
+        self.prefix.cmp(&other.prefix).then_with(|| self.record(data).cmp(other.record(other_data)))
+    }
+}
+
+
+/// [Iterator] over the records of a [RecordBuf]
+///
+/// Instances of this type are created via [RecordBuf::iter] and
+/// [RecordBuf::iter_desc].
+#[derive(Debug)]
+pub struct Records<'a> {
+    heads: BinaryHeap<Head<&'a RecordBucket>>,
+    remaining: usize,
+}
+
+impl<'a> Records<'a> {
+    /// Create a new [Iterator] over the records in the given buckets
+    fn new(buckets: &'a [RecordBucket], descending: bool) -> Self {
+        let heads = buckets.iter().filter(|b| !b.is_empty()).map(|b| Head::new(b, descending)).collect();
+        Self{heads, remaining: buckets.iter().map(RecordBucket::len).sum()}
+    }
+}
+
+impl ExactSizeIterator for Records<'_> {}
+
+impl FusedIterator for Records<'_> {}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut head = self.heads.peek_mut()?;
+        let bucket = head.bucket;
+        let pos = head.advance()?;
+
+        if head.range.is_empty() {
+            binary_heap::PeekMut::pop(head);
+        }
+        self.remaining -= 1;
+        Some(bucket.get(&bucket.index[pos]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+/// Consuming [Iterator] over the records of a [RecordBuf]
+///
+/// Instances of this type are created via [RecordBuf::into_iter] and
+/// [RecordBuf::into_iter_desc]. Each [RecordBucket] is dropped, releasing its
+/// memory, as soon as all of its records were yielded.
+#[derive(Debug)]
+pub struct IntoRecords {
+    heads: BinaryHeap<Head<RecordBucket>>,
+    remaining: usize,
+}
+
+impl IntoRecords {
+    /// Create a new [Iterator] over the records in the given buckets
+    fn new(buckets: Vec<RecordBucket>, descending: bool) -> Self {
+        let remaining = buckets.iter().map(RecordBucket::len).sum();
+        let heads = buckets.into_iter().filter(|b| !b.is_empty()).map(|b| Head::new(b, descending)).collect();
+        Self{heads, remaining}
+    }
+}
+
+impl ExactSizeIterator for IntoRecords {}
+
+impl FusedIterator for IntoRecords {}
+
+impl Iterator for IntoRecords {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut head = self.heads.peek_mut()?;
+        let pos = head.advance()?;
+        let record = head.bucket.get(&head.bucket.index[pos]).to_vec();
+
+        if head.range.is_empty() {
+            binary_heap::PeekMut::pop(head);
+        }
+        self.remaining -= 1;
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+/// Remaining records of a single [RecordBucket] during iteration
+///
+/// Instances are ordered by their current record in the direction of the
+/// iteration, i.e. the instance holding the next record is the greatest. The
+/// [RecordBucket] may be either borrowed or owned.
+#[derive(Debug)]
+struct Head<B: Borrow<RecordBucket>> {
+    bucket: B,
+    range: Range<usize>,
+    descending: bool,
+}
+
+impl<B: Borrow<RecordBucket>> Head<B> {
+    /// Create a new instance covering all records of the given bucket
+    fn new(bucket: B, descending: bool) -> Self {
+        let range = 0..bucket.borrow().len();
+        Self{bucket, range, descending}
+    }
+
+    /// Advance to the next record, returning the position of the current one
+    fn advance(&mut self) -> Option<usize> {
+        if self.descending { self.range.next_back() } else { self.range.next() }
+    }
+
+    /// Retrieve the index entry of the current record
+    fn current(&self) -> &Entry {
+        let pos = if self.descending { self.range.end - 1 } else { self.range.start };
+        &self.bucket.borrow().index[pos]
+    }
+}
+
+impl<B: Borrow<RecordBucket>> Ord for Head<B> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let data = &self.bucket.borrow().data;
+        let ord = self.current().compare(other.current(), data, &other.bucket.borrow().data);
+        if self.descending { ord } else { ord.reverse() }
+    }
+}
+
+impl<B: Borrow<RecordBucket>> PartialOrd for Head<B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<B: Borrow<RecordBucket>> Eq for Head<B> {}
+
+impl<B: Borrow<RecordBucket>> PartialEq for Head<B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+/// Record feeder for [RecordAccumulator]s
+///
+/// Instances of this type collect records into [RecordBucket]s of a target
+/// size in bytes, including the index. Once a [RecordBucket] is full, it is
+/// sorted and committed to the [RecordAccumulator]. The remaining records are
+/// committed when the `RecordInserter` is dropped.
+///
+/// # Examples
+///
+/// ```
+/// let mut records = sortbuf::RecordBuf::new();
+/// let mut inserter = sortbuf::RecordInserter::new(&mut records);
+/// inserter.set_bucket_bytesize(64);
+/// inserter.insert_records((0..100).map(|i| format!("{i:03}"))).expect("Failed to insert records");
+/// drop(inserter);
+///
+/// assert!(records.iter().map(|r| std::str::from_utf8(r).unwrap()).eq((0..100).map(|i| format!("{i:03}"))));
+/// ```
+#[derive(Debug)]
+pub struct RecordInserter<A: RecordAccumulator> {
+    bucket: RecordBucket,
+    accumulator: A,
+    bucket_bytesize: usize,
+}
+
+impl<A: RecordAccumulator> RecordInserter<A> {
+    /// Create a new `RecordInserter` with a default target bucket size
+    ///
+    /// Create a new `RecordInserter` for the given `accumulator`, committing
+    /// [RecordBucket]s of a [default size](DEFAULT_BUCKET_BYTESIZE).
+    pub fn new(accumulator: A) -> Self {
+        Self{bucket: Default::default(), accumulator, bucket_bytesize: DEFAULT_BUCKET_BYTESIZE}
+    }
+
+    /// Set a new target bucket size in bytes
+    ///
+    /// After calling this function, this inserter will commit [RecordBucket]s
+    /// near `bytesize` bytes in size, including the index. The storage
+    /// allocated for a [RecordBucket] never exceeds that size unless it
+    /// contains only a single record which does.
+    pub fn set_bucket_bytesize(&mut self, bytesize: usize) -> &mut Self {
+        self.bucket_bytesize = bytesize;
+        self
+    }
+
+    /// Insert a single record
+    ///
+    /// If the insertion fails due to an allocation failure, an error is
+    /// returned and the record is not inserted.
+    pub fn insert(&mut self, record: &[u8]) -> Result<(), InsertionError> {
+        if !self.bucket.push(record, self.bucket_bytesize)? {
+            self.commit()?;
+            self.bucket.push(record, self.bucket_bytesize)?;
+        }
+        Ok(())
+    }
+
+    /// Insert records into the accumulator
+    ///
+    /// This function inserts the given `records`. If the insertion fails due
+    /// to an allocation failure, an error is returned. All records consumed
+    /// from the [Iterator] before the failing one are inserted.
+    pub fn insert_records<R: AsRef<[u8]>>(
+        &mut self,
+        records: impl IntoIterator<Item = R>,
+    ) -> Result<(), InsertionError> {
+        records.into_iter().try_for_each(|r| self.insert(r.as_ref()))
+    }
+
+    /// Insert all lines read from the given `reader`
+    ///
+    /// Lines are terminated by `\n` or `\r\n`, which is not part of the record
+    /// inserted. Contrary to [BufRead::lines](std::io::BufRead::lines), lines
+    /// need not be valid UTF-8 and no allocation is performed per line.
+    /// [InsertionError]s are reported as [std::io::Error]s.
+    #[cfg(feature = "std")]
+    pub fn insert_lines(&mut self, mut reader: impl std::io::BufRead) -> std::io::Result<()> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(())
+            }
+
+            let record = match line.strip_suffix(b"\n") {
+                Some(record) => record.strip_suffix(b"\r").unwrap_or(record),
+                None => &line,
+            };
+            self.insert(record).map_err(std::io::Error::other)?;
+        }
+    }
+
+    /// Commit all records held by this inserter
+    ///
+    /// This function sorts and commits the current [RecordBucket] even if it
+    /// is not full. If committing fails, an error is returned and the records
+    /// remain in this inserter.
+    pub fn flush(&mut self) -> Result<(), InsertionError> {
+        self.commit()
+    }
+
+    /// Retrieve the number of bytes allocated for records not yet committed
+    pub fn bytesize(&self) -> usize {
+        self.bucket.bytesize()
+    }
+
+    /// Retrieve a reference to the underlying [RecordAccumulator]
+    pub fn record_accumulator(&self) -> &A {
+        &self.accumulator
+    }
+
+    /// Sort, shrink and commit the current bucket
+    ///
+    /// Even full buckets are shrunk, since the records or the index may not
+    /// have used up their storage.
+    fn commit(&mut self) -> Result<(), InsertionError> {
+        if self.bucket.is_empty() {
+            return Ok(())
+        }
+
+        let mut bucket = core::mem::take(&mut self.bucket);
+        bucket.sort();
+        bucket.data.shrink_to_fit();
+        bucket.index.shrink_to_fit();
+        self.accumulator.add_record_bucket(bucket).map_err(|(e, b)| {
+            self.bucket = b;
+            e
+        })
+    }
+}
+
+impl<A: RecordAccumulator> Drop for RecordInserter<A> {
+    fn drop(&mut self) {
+        self.flush().expect("Failed to add final bucket")
+    }
+}
+
+
+/// Compute the share of `limit` proportional to `part` out of `part` and `rest`
+fn share(limit: usize, part: usize, rest: usize) -> usize {
+    let total = (part as u128) + (rest as u128);
+    if total == 0 {
+        return 0
+    }
+    ((limit as u128) * (part as u128) / total) as usize
+}
+
+
+/// Grow `items` to hold at least `additional` more items
+///
+/// The capacity is doubled if possible, but limited to `max_capacity` unless
+/// more items need to be held.
+fn grow<T>(items: &mut Vec<T>, additional: usize, max_capacity: usize) -> Result<(), InsertionError> {
+    let needed = items.len().saturating_add(additional);
+    if needed <= items.capacity() {
+        return Ok(())
+    }
+
+    let capacity = core::cmp::min(items.capacity().saturating_mul(2), max_capacity);
+    items.try_reserve_exact(core::cmp::max(capacity, needed) - items.len())?;
+    Ok(())
+}
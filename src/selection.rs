@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: MIT
+//! Replacement selection for generating long sorted runs
+
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::num::NonZeroUsize;
+
+use super::{BucketAllocator, Global};
+use super::bucket::{self, Bucket};
+use super::error::InsertionError;
+use super::inserter::BucketAccumulator;
+
+
+/// Item feeder for [BucketAccumulator]s generating long runs
+///
+/// Like an [Inserter](super::Inserter), instances of this type collect items
+/// into [Bucket]s and commit them to a [BucketAccumulator]. However, rather
+/// than collecting fixed-size chunks of items and sorting them, a `RunInserter`
+/// uses replacement selection: it maintains a heap of up to a target number of
+/// items and extends the current sorted run with the least item which is not
+/// less than the run's last item. Only once no such item is left in the heap,
+/// the run is committed as a [Bucket].
+///
+/// On random input, this results in runs about twice the size of the heap. On
+/// nearly sorted input, runs may be arbitrarily long. Fewer, longer [Bucket]s
+/// directly benefit the performance of the final iteration.
+///
+/// # Time complexity
+///
+/// A call to [insert_items](Self::insert_items) comes with an estimated runtime
+/// cost of O(_n_ log(_h_)) with _n_ denoting the number of items to insert and
+/// _h_ denoting the target heap size.
+///
+/// # Memory
+///
+/// Unlike an [Inserter](super::Inserter), a `RunInserter` holds the current
+/// run in addition to the heap. As the length of a run is not known in advance,
+/// its storage grows via reallocation, in steps of the target heap size. Thus,
+/// the memory held by a `RunInserter` may exceed the target size considerably.
+///
+/// The storage of runs is allocated through a [BucketAllocator], by default the
+/// [Global] allocator. A different allocator may be supplied via
+/// [with_allocator](Self::with_allocator). The heap itself is always allocated
+/// via the global allocator.
+#[derive(Debug)]
+pub struct RunInserter<A: BucketAccumulator, M: BucketAllocator<A::Item> = Global> {
+    heap: BinaryHeap<Reverse<(usize, A::Item)>>,
+    run: Vec<A::Item>,
+    run_id: usize,
+    bucket_accumulator: A,
+    heap_size: NonZeroUsize,
+    allocator: M,
+}
+
+impl<A: BucketAccumulator> RunInserter<A> {
+    /// Create a new `RunInserter` with a default heap target size
+    ///
+    /// Create a new `RunInserter` for the given `bucket_accumulator`. The heap
+    /// will hold a number of items near the bucket size
+    /// [preferred](BucketAccumulator::preferred_bucket_size) by the
+    /// accumulator or, lacking a preference, a
+    /// [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
+    pub fn new(bucket_accumulator: A) -> Self {
+        Self::with_allocator(bucket_accumulator, Global)
+    }
+}
+
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> RunInserter<A, M> {
+    /// Create a new `RunInserter` allocating runs via the given allocator
+    ///
+    /// Create a new `RunInserter` for the given `bucket_accumulator`. The
+    /// storage for runs committed to that [BucketAccumulator] will be allocated
+    /// via the given `allocator`. The heap will hold a number of items near the
+    /// bucket size [preferred](BucketAccumulator::preferred_bucket_size) by the
+    /// accumulator or, lacking a preference, a
+    /// [default bucket size](bucket::DEFAULT_BUCKET_BYTESIZE).
+    pub fn with_allocator(bucket_accumulator: A, allocator: M) -> Self {
+        Self{
+            heap: Default::default(),
+            run: Default::default(),
+            run_id: 0,
+            heap_size: bucket_accumulator
+                .preferred_bucket_size()
+                .unwrap_or_else(|| Self::size_from_bytesize(bucket::DEFAULT_BUCKET_BYTESIZE)),
+            bucket_accumulator,
+            allocator,
+        }
+    }
+
+    /// Insert items into the accumulator
+    ///
+    /// This function inserts the given `items` to the buffer. If the insertion
+    /// fails due to an (re-)allocation failure, an error is returned.
+    ///
+    /// Even in the event of such an error, all items consumed from the
+    /// `Iterator` passed to this method will reside either in the underlying
+    /// [BucketAccumulator] or the `RunInserter`s internal storage after the
+    /// operation. Thus, callers can recover from allocation failures without
+    /// any data loss by passing a mutable reference to an [Iterator] rather
+    /// than a value, e.g. the result of [Iterator::by_ref].
+    pub fn insert_items(&mut self, items: impl IntoIterator<Item = A::Item>) -> Result<(), InsertionError> {
+        let mut items = items.into_iter().fuse();
+
+        loop {
+            while self.heap.len() >= self.heap_size.get() {
+                self.select()?;
+            }
+
+            // The heap never holds more than its target size, so we reserve
+            // exactly that.
+            self.heap.try_reserve_exact(self.heap_size.get() - self.heap.len())?;
+            let Some(item) = items.next() else { break };
+            let run_id = match self.run.last() {
+                Some(last) if item < *last  => self.run_id + 1,
+                _                           => self.run_id,
+            };
+            self.heap.push(Reverse((run_id, item)));
+        }
+
+        Ok(())
+    }
+
+    /// Set a new target heap size
+    ///
+    /// After calling this function, this inserter will hold up to `size` items
+    /// in its heap.
+    pub fn set_bucket_size(&mut self, size: NonZeroUsize) -> &mut Self {
+        self.heap_size = size;
+        self
+    }
+
+    /// Set a new target heap size in bytes
+    ///
+    /// After calling this function, this inserter will hold up to `bytesize`
+    /// bytes worth of items in its heap, including the ids of their runs.
+    pub fn set_bucket_bytesize(&mut self, bytesize: usize) -> &mut Self {
+        self.heap_size = Self::size_from_bytesize(bytesize);
+        self
+    }
+
+    /// Get the current target heap size in items
+    pub fn bucket_size(&self) -> NonZeroUsize {
+        self.heap_size
+    }
+
+    /// Move the least eligible item from the heap to the current run
+    ///
+    /// If the current run is exhausted, it is committed to the accumulator and
+    /// a new run is started. In the event of an error, no item is moved.
+    fn select(&mut self) -> Result<(), InsertionError> {
+        let Some(run_id) = self.heap.peek().map(|Reverse((r, _))| *r) else { return Ok(()) };
+        if run_id != self.run_id {
+            self.commit()?;
+            self.run_id = run_id;
+        }
+
+        if self.run.len() >= self.run.capacity() {
+            self.grow_run()?;
+        }
+        if let Some(Reverse((_, item))) = self.heap.pop() {
+            self.run.push(item)
+        }
+        Ok(())
+    }
+
+    /// Grow the storage of the current run by the target heap size
+    ///
+    /// On random input, runs are about twice the size of the heap. Growing in
+    /// steps of that size rather than doubling the capacity limits the storage
+    /// left unused at the end of a run.
+    fn grow_run(&mut self) -> Result<(), InsertionError> {
+        if self.run.capacity() == 0 {
+            self.run = self.allocator.allocate(self.heap_size.get())?;
+            Ok(())
+        } else {
+            self.allocator.grow(&mut self.run, self.heap_size.get())
+        }
+    }
+
+    /// Commit the current run to the accumulator
+    fn commit(&mut self) -> Result<(), InsertionError> {
+        let mut run = core::mem::take(&mut self.run);
+        if run.is_empty() {
+            self.allocator.release(run);
+            return Ok(())
+        }
+
+        // Items are moved to the run in ascending order.
+        self.allocator.shrink(&mut run, 0);
+        self.bucket_accumulator.add_bucket(Bucket::from_sorted_unchecked(run)).map_err(|(e, b)| {
+            self.run = b.into_vec();
+            e
+        })
+    }
+
+    /// Determine the heap target size for a given bytesize
+    ///
+    /// Each item in the heap is accompanied by the id of its run.
+    fn size_from_bytesize(bytesize: usize) -> NonZeroUsize {
+        NonZeroUsize::new(bytesize / core::cmp::max(core::mem::size_of::<(usize, A::Item)>(), 1))
+            .or(NonZeroUsize::new(1))
+            .expect("Could not compute heap size")
+    }
+}
+
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> Extend<A::Item> for RunInserter<A, M> {
+    fn extend<I: IntoIterator<Item = A::Item>>(&mut self, iter: I) {
+        self.insert_items(iter).expect("Failed to insert items")
+    }
+}
+
+impl<A: BucketAccumulator, M: BucketAllocator<A::Item>> Drop for RunInserter<A, M> {
+    fn drop(&mut self) {
+        while !self.heap.is_empty() {
+            self.select().expect("Failed to flush heap")
+        }
+        self.commit().expect("Failed to add final bucket");
+        self.bucket_accumulator.on_inserter_finished()
+    }
+}
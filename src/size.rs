@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+//! Utilities for accounting for memory owned by items
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use super::KeyValue;
+
+
+/// Items owning memory on the heap
+///
+/// This trait allows determining the memory used by an item beyond its inline
+/// size, i.e. [core::mem::size_of]. It enables [Inserter](super::Inserter)s to
+/// size [Bucket](super::Bucket)s according to the memory actually used via
+/// [heap accounting](super::Inserter::set_heap_accounting).
+///
+/// Implementations report the memory _allocated_, e.g. the capacity rather than
+/// the length of a [Vec], but exclude any overhead of the allocator itself.
+///
+/// # Examples
+///
+/// ```
+/// use sortbuf::HeapSize;
+///
+/// assert_eq!(42u64.heap_size(), 0);
+/// assert_eq!(String::with_capacity(100).heap_size(), 100);
+/// assert_eq!((1u8, String::with_capacity(10)).deep_size(), 10 + std::mem::size_of::<(u8, String)>());
+/// ```
+pub trait HeapSize {
+    /// Retrieve the number of bytes owned by this value on the heap
+    fn heap_size(&self) -> usize;
+
+    /// Retrieve the number of bytes used by this value, including its own size
+    fn deep_size(&self) -> usize {
+        core::mem::size_of_val(self).saturating_add(self.heap_size())
+    }
+}
+
+/// Implement [HeapSize] for types not owning any memory on the heap
+macro_rules! impl_inline {
+    ($($t:ty),*) => {$(
+        impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        }
+    )*}
+}
+
+impl_inline!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char, (), str);
+
+/// Implement [HeapSize] for tuples of the given element type parameters
+macro_rules! impl_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: HeapSize),*> HeapSize for ($($t,)*) {
+            #[allow(non_snake_case)]
+            fn heap_size(&self) -> usize {
+                let ($($t,)*) = self;
+                0usize $(.saturating_add($t.heap_size()))*
+            }
+        }
+    }
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for [T] {
+    fn heap_size(&self) -> usize {
+        self.iter().fold(0, |s, i| s.saturating_add(i.heap_size()))
+    }
+}
+
+impl<T: HeapSize, const N: usize> HeapSize for [T; N] {
+    fn heap_size(&self) -> usize {
+        self.as_slice().heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        (self.capacity() * core::mem::size_of::<T>()).saturating_add(self.as_slice().heap_size())
+    }
+}
+
+impl<T: HeapSize + ?Sized> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        T::deep_size(self)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map(T::heap_size).unwrap_or(0)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Reverse<T> {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for KeyValue<K, V> {
+    fn heap_size(&self) -> usize {
+        self.key.heap_size().saturating_add(self.value.heap_size())
+    }
+}
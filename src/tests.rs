@@ -1,5 +1,5 @@
 // SPDX-License-Identifier: MIT
-/// Tests
+//! Tests
 
 use super::*;
 
@@ -70,13 +70,445 @@ fn inserter_multiple() {
 }
 
 
+#[test]
+fn inserter_vec() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.insert_vec(random_items(1000).collect()).expect("Failed to insert items");
+        inserter.insert_vec((0..1000).rev().collect()).expect("Failed to insert items");
+        inserter.insert_sorted_vec((0..1000).collect()).expect("Failed to insert items");
+        inserter.insert_sorted_vec(random_items(500).collect()).expect("Failed to insert items");
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 3500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[test]
+fn inserter_hooks() {
+    #[derive(Default)]
+    struct Acc {
+        buf: SortBuf<Item>,
+        batches: usize,
+        reserved: usize,
+        finished: bool,
+    }
+
+    impl BucketAccumulator for Acc {
+        type Item = Item;
+
+        fn add_bucket(&mut self, bucket: Bucket<Item>) -> error::InsertionResult<Bucket<Item>> {
+            self.buf.add_bucket(bucket)
+        }
+
+        fn add_buckets(
+            &mut self,
+            buckets: impl IntoIterator<Item = Bucket<Item>>,
+        ) -> error::InsertionResult<Bucket<Item>> {
+            self.batches += 1;
+            self.buf.add_buckets(buckets)
+        }
+
+        fn reserve_buckets(&mut self, additional: usize) -> Result<(), error::InsertionError> {
+            self.reserved += additional;
+            Ok(())
+        }
+
+        fn preferred_bucket_size(&self) -> Option<NonZeroUsize> {
+            NonZeroUsize::new(1000)
+        }
+
+        fn on_inserter_finished(&mut self) {
+            self.finished = true
+        }
+    }
+
+    let mut acc: Acc = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut acc);
+        assert_eq!(inserter.bucket_size().get(), 1000);
+        inserter.insert_items((0..2_500).collect::<Vec<_>>()).expect("Failed to insert items");
+        assert_eq!(inserter.bucket_accumulator().batches, 0);
+        inserter.set_max_pending_buckets(8);
+        inserter.insert_items((2_500..10_500).collect::<Vec<_>>()).expect("Failed to insert items");
+    }
+
+    assert_eq!(acc.reserved, 10);
+    assert_eq!(acc.batches, 1);
+    assert!(acc.finished);
+    assert_eq!(acc.buf.into_iter().len(), 10_500);
+}
+
+#[test]
+fn inserter_recovering() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let acc = adapters::Counting::new(adapters::FailAfter::new(&mut buf, 3));
+        let mut inserter = inserter::Inserter::new(acc);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+
+        let mut items = random_items(10_500);
+        assert!(inserter.insert_items(items.by_ref()).is_err());
+        assert_eq!(inserter.bucket_accumulator().buckets(), 3);
+        assert!(inserter.insert_items(items.by_ref()).is_err());
+
+        inserter.bucket_accumulator_mut().inner_mut().set_remaining(usize::MAX);
+        inserter.insert_items(items).expect("Failed to insert items");
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[test]
+fn counting_buffered() {
+    // Accumulator consuming all buckets before rejecting the first one
+    #[derive(Default)]
+    struct RejectFirst(Vec<Bucket<Item>>);
+
+    impl BucketAccumulator for RejectFirst {
+        type Item = Item;
+
+        fn add_bucket(&mut self, bucket: Bucket<Item>) -> error::InsertionResult<Bucket<Item>> {
+            Err((error::InsertionError::limit_exceeded(), bucket))
+        }
+
+        fn add_buckets(
+            &mut self,
+            buckets: impl IntoIterator<Item = Bucket<Item>>,
+        ) -> error::InsertionResult<Bucket<Item>> {
+            let mut buckets = buckets.into_iter();
+            let first = buckets.next();
+            self.0.extend(buckets);
+            first.map_or(Ok(()), |b| self.add_bucket(b))
+        }
+    }
+
+    let mut counting = adapters::Counting::new(RejectFirst::default());
+    let buckets = [10, 20, 30].map(|n| Bucket::from_unsorted(random_items(n).collect()));
+    assert!(counting.add_buckets(buckets).is_err());
+    assert_eq!(counting.buckets(), 2);
+    assert_eq!(counting.items(), 50);
+}
+
+#[test]
+fn inserter_single() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        random_items(10_500).try_for_each(|i| inserter.insert(i)).map_err(|(e, _)| e).expect("Failed to insert items");
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[test]
+fn inserter_slice() {
+    let items: Vec<_> = random_items(10_500).collect();
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(adapters::FailAfter::new(&mut buf, 2));
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        let (_, rest) = inserter.insert_slice(&items).expect_err("Inserted despite injected failure");
+        assert!(!rest.is_empty());
+
+        inserter.bucket_accumulator_mut().set_remaining(usize::MAX);
+        inserter.insert_slice(rest).map_err(|(e, _)| e).expect("Failed to insert items");
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[test]
+fn inserter_fallible() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+
+        let mut items = random_items(10_500).enumerate().map(|(n, i)| if n % 1000 == 999 { Err(n) } else { Ok(i) });
+        let mut errors = 0;
+        while let Err(e) = inserter.try_insert_items(items.by_ref()) {
+            assert!(matches!(e, error::TryInsertError::Source(_)));
+            errors += 1;
+        }
+        assert_eq!(errors, 10);
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_490);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn inserter_flushing() {
+    let buf: std::sync::Mutex<SortBuf<_>> = Default::default();
+    let mut inserter = inserter::Inserter::new(&buf);
+    inserter.set_max_items(NonZeroUsize::new(100));
+
+    // Buckets are sized for the maximum number of items.
+    inserter.insert_items(random_items(250)).expect("Failed to insert items");
+    inserter.insert_items(random_items(30)).expect("Failed to insert items");
+    assert_eq!(buf.lock().expect("Could not lock mutex!").take().into_iter().len(), 200);
+
+    inserter.flush().expect("Failed to flush");
+    assert_eq!(buf.lock().expect("Could not lock mutex!").take().into_iter().len(), 80);
+
+    inserter.set_max_items(None).set_max_age(Some(std::time::Duration::ZERO));
+    inserter.insert_items(random_items(10)).expect("Failed to insert items");
+    assert_eq!(buf.lock().expect("Could not lock mutex!").take().into_iter().len(), 10);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn inserter_aging() {
+    let max_age = std::time::Duration::from_millis(50);
+    let buf: std::sync::Mutex<SortBuf<_>> = Default::default();
+    let mut inserter = inserter::Inserter::new(&buf);
+    inserter.set_max_age(Some(max_age));
+
+    inserter.insert(1).expect("Failed to insert item");
+    std::thread::sleep(max_age * 2);
+    inserter.insert(2).expect("Failed to insert item");
+    assert!(buf.lock().expect("Could not lock mutex!").take().into_iter().eq([1]));
+
+    std::thread::sleep(max_age * 2);
+    inserter.flush_if_due().expect("Failed to flush");
+    assert!(buf.lock().expect("Could not lock mutex!").take().into_iter().eq([2]));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pool_reusing() {
+    let pool = BucketPool::new(4);
+    let items: Vec<Item> = pool.allocate(1000).expect("Failed to allocate");
+    let ptr = items.as_ptr();
+    pool.release(items);
+    assert_eq!(pool.len(), 1);
+
+    let items = pool.allocate(500).expect("Failed to allocate");
+    assert_eq!(items.as_ptr(), ptr);
+    assert!(pool.is_empty());
+
+    for _ in 0..3 {
+        let mut buf: SortBuf<_> = Default::default();
+        {
+            let mut inserter = inserter::Inserter::with_allocator(&mut buf, &pool);
+            inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+            inserter.insert_items(random_items(10_500)).expect("Failed to insert items");
+        }
+
+        let iter = buf.into_iter().with_allocator(&pool);
+        assert_eq!(iter.len(), 10_500);
+        assert_sorted(iter.map(Reverse));
+        assert!(pool.len() <= 4);
+    }
+}
+
+
+#[test]
+fn run_inserter_random() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = RunInserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.extend(random_items(10_500));
+    }
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[test]
+fn run_inserter_presorted() {
+    let mut buf: SortBuf<Item> = Default::default();
+    {
+        let mut inserter = RunInserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(100).expect("Failed to construct bucket size"));
+        inserter.extend((0..10_000).map(|i| i + (i % 7) * 10));
+    }
+
+    let runs: Vec<_> = buf.into_iter().drain_runs().collect();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].len(), 10_000);
+}
+
+#[test]
+fn run_inserter_limited() {
+    let size = core::mem::size_of::<Item>();
+    let limit = MemoryLimit::new(20_000 * size);
+    let mut buf: SortBuf<Item, _> = SortBuf::with_allocator(&limit);
+    {
+        let mut inserter = RunInserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_size(NonZeroUsize::new(100).expect("Failed to construct bucket size"));
+        inserter.insert_items(0..10_000).expect("Failed to insert items");
+        // The run grows in steps of the heap size rather than by doubling
+        assert!(limit.limit() - limit.available() <= 10_000 * size);
+    }
+    assert_eq!(limit.limit() - limit.available(), 10_000 * size);
+    assert!(buf.take().into_iter().eq((0..10_000).rev()));
+    assert_eq!(limit.available(), limit.limit());
+}
+
+
+#[test]
+fn inserter_unreserved() {
+    struct Unreserved<'a>(&'a mut SortBuf<Item>);
+
+    impl BucketAccumulator for Unreserved<'_> {
+        type Item = Item;
+
+        fn add_bucket(&mut self, bucket: Bucket<Item>) -> error::InsertionResult<Bucket<Item>> {
+            self.0.add_bucket(bucket)
+        }
+
+        fn reserve_buckets(&mut self, _: usize) -> Result<(), error::InsertionError> {
+            Err(error::InsertionError::injected())
+        }
+    }
+
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(Unreserved(&mut buf));
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_items(random_items(10_500)).expect("Failed to insert items");
+    }
+    assert_eq!(buf.into_iter().len(), 10_500);
+}
+
+#[test]
+fn inserter_flushing_limited() {
+    let size = core::mem::size_of::<Item>();
+    let limit = MemoryLimit::new(2 * DEFAULT_BUCKET_BYTESIZE);
+    let mut buf = SortBuf::with_allocator(&limit);
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_max_items(NonZeroUsize::new(100));
+        inserter.insert_items(random_items(50)).expect("Failed to insert items");
+        assert_eq!(limit.limit() - limit.available(), 100 * size);
+        // Three full buckets and a fresh, empty one
+        inserter.insert_items(random_items(250)).expect("Failed to insert items");
+        assert_eq!(limit.limit() - limit.available(), 400 * size);
+    }
+    assert_eq!(buf.into_iter().len(), 300);
+    assert_eq!(limit.available(), limit.limit());
+}
+
+#[test]
+fn inserter_limited() {
+    let limit = MemoryLimit::new(10 * 1000 * core::mem::size_of::<Item>());
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        assert!(inserter.insert_items(random_items(10_500)).is_err());
+        assert_eq!(limit.available(), 0);
+    }
+
+    let iter = buf.into_iter().with_allocator(&limit);
+    assert_eq!(iter.len(), 10_000);
+    assert_sorted(iter.map(Reverse));
+    assert_eq!(limit.available(), limit.limit());
+}
+
+#[test]
+fn inserter_degrading() {
+    let limit = MemoryLimit::new(2500 * core::mem::size_of::<Item>());
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.set_min_bucket_size(NonZeroUsize::new(100));
+        inserter.insert_items(random_items(2400)).expect("Failed to insert items");
+        assert!(inserter.degraded());
+        assert_eq!(inserter.bucket_size().get(), 500);
+    }
+
+    assert_eq!(buf.into_iter().with_allocator(&limit).len(), 2400);
+}
+
+#[test]
+fn inserter_limited_cycle() {
+    let size = core::mem::size_of::<Item>();
+    let limit = MemoryLimit::new(10_000 * size);
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_items(random_items(3000)).expect("Failed to insert items");
+
+        let mut upper = Vec::with_capacity(2000);
+        upper.extend((0..1500).map(|i| Item::MAX - i));
+        inserter.insert_vec(upper).expect("Failed to insert items");
+        assert!(inserter.insert_vec(Vec::with_capacity(6000)).is_ok());
+        assert!(inserter.insert_vec(vec![0; 6000]).is_err());
+    }
+    assert_eq!(limit.available(), 5000 * size);
+
+    let mut iter = buf.into_iter().with_allocator(&limit);
+    let run = iter.next_batch().expect("No run retrieved");
+    assert_eq!(run.len(), 1500);
+    assert_eq!(limit.available(), 7000 * size);
+    assert_eq!(iter.len(), 3000);
+    assert_sorted(iter.map(Reverse));
+    assert_eq!(limit.available(), limit.limit());
+}
+
+#[test]
+fn buffer_limited() {
+    let limit = MemoryLimit::new(10_000 * core::mem::size_of::<Item>());
+    let mut buf = SortBuf::with_allocator(&limit);
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_items(random_items(4500)).expect("Failed to insert items");
+    }
+
+    // Items split off from buckets are allocated and then handed out.
+    let mut upper = buf.drain_from(&(Item::MAX / 2)).expect("Failed to drain items");
+    let runs: Vec<_> = upper.drain_runs().collect();
+    drop(upper);
+    assert!(runs.iter().all(|r| r.iter().all(|i| *i >= Item::MAX / 2)));
+
+    let items = buf.into_sorted_vec().expect("Failed to merge buckets");
+    assert_sorted(items.iter());
+    assert_eq!(runs.iter().map(Vec::len).sum::<usize>() + items.len(), 4500);
+    assert_eq!(limit.available(), limit.limit());
+}
+
+
+#[test]
+fn sorted_vec() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.extend(random_items(10_500));
+    }
+
+    let mut expected: Vec<_> = random_items(10_500).collect();
+    expected.sort_unstable();
+    assert_eq!(buf.into_sorted_vec().expect("Failed to merge buckets"), expected);
+}
+
+
 #[test]
 fn iter_sorted() {
     let mut items = random_items(10_500);
 
     let iter: iter::Iter<Item> = std::iter::from_fn(
         move || Some(items.by_ref().take(1000).collect::<Vec<_>>())
-    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::new).map(Into::into).collect::<Vec<_>>().into();
+    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::from_unsorted).map(Into::into).collect::<Vec<_>>().into();
 
     assert_sorted(iter.map(Reverse))
 }
@@ -87,18 +519,643 @@ fn iter_count() {
 
     let iter: iter::Iter<Item> = std::iter::from_fn(
         move || Some(items.by_ref().take(1000).collect::<Vec<_>>())
-    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::new).map(Into::into).collect::<Vec<_>>().into();
+    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::from_unsorted).map(Into::into).collect::<Vec<_>>().into();
 
     assert_eq!(iter.count(), 10_500)
 }
 
+#[test]
+fn iter_shrinking() {
+    let mut items = random_items(100_500);
+
+    let iter: iter::Iter<Item> = std::iter::from_fn(
+        move || Some(items.by_ref().take(10_000).collect::<Vec<_>>().into_boxed_slice().into_vec())
+    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::from_unsorted).map(Into::into).collect::<Vec<_>>().into();
+
+    let mut iter = iter.with_shrink_threshold_bytes(4096);
+    assert_eq!(iter.len(), 100_500);
+
+    // Each of the 11 buckets may retain less than the threshold.
+    let max_overcapacity = 11 * 4096 / core::mem::size_of::<Item>();
+    let items: Vec<_> = core::iter::from_fn(|| {
+        let item = iter.next();
+        assert!(iter.overcapacity() < max_overcapacity);
+        item
+    }).collect();
+    assert_eq!(items.len(), 100_500);
+    assert_sorted(items.into_iter().map(Reverse))
+}
+
+#[test]
+fn iter_runs() {
+    let mut items = random_items(10_500);
+
+    let mut iter: iter::Iter<Item> = std::iter::from_fn(
+        move || Some(items.by_ref().take(1000).collect::<Vec<_>>())
+    ).take_while(|v| !v.is_empty()).map(bucket::Bucket::from_unsorted).map(Into::into).collect::<Vec<_>>().into();
+
+    let runs: Vec<_> = iter.drain_runs().collect();
+    assert!(runs.iter().all(|r| !r.is_empty()));
+    assert_eq!(runs.iter().map(Vec::len).sum::<usize>(), 10_500);
+    assert_sorted(runs.into_iter().flatten().map(Reverse))
+}
+
+#[test]
+fn iter_runs_reusing() {
+    let items = random_items(1000).collect::<Box<[_]>>().into_vec();
+    let bucket: bucket::SortedBucket<Item> = bucket::Bucket::from_unsorted(items).into();
+    let mut iter: iter::Iter<Item> = vec![bucket].into();
+    let run = iter.try_next_batch().expect("Failed to retrieve run").expect("No run retrieved");
+    assert_eq!(run.len(), 1000);
+    assert_eq!(run.capacity(), 1000);
+    assert_eq!(iter.try_next_batch().expect("Failed to retrieve run"), None);
+    assert_sorted(run.into_iter().map(Reverse))
+}
+
+#[test]
+fn iter_pushing() {
+    let mut buf: SortBuf<_> = Default::default();
+    inserter::Inserter::new(&mut buf).insert_items(random_items(10_500)).expect("Failed to insert items");
+    let mut reference: std::collections::BinaryHeap<_> = random_items(10_500).collect();
+
+    let mut iter = buf.into_iter();
+    let mut items = random_items(21_000).skip(10_500);
+    for n in 0..1000 {
+        items.by_ref().take(5).for_each(|i| {
+            iter.push(i).expect("Failed to push item");
+            reference.push(i);
+        });
+        if n % 10 == 0 {
+            let bucket: Vec<_> = items.by_ref().take(50).collect();
+            reference.extend(bucket.iter().copied());
+            inserter::Inserter::new(&mut iter).insert_vec(bucket).expect("Failed to insert items");
+        }
+        if n % 7 == 0 {
+            let run = iter.next_batch().expect("No run retrieved");
+            let expected: Vec<_> = core::iter::from_fn(|| reference.pop()).take(run.len()).collect();
+            assert_eq!(run, expected);
+        }
+        assert_eq!(iter.next(), reference.pop());
+        assert_eq!(iter.len(), reference.len());
+    }
+
+    let buf: SortBuf<_> = iter.into();
+    assert!(buf.into_iter().eq(reference.into_sorted_vec().into_iter().rev()));
+}
+
+#[test]
+fn iter_pushing_limited() {
+    let limit = MemoryLimit::new(16_000);
+    let mut other = SortBuf::with_allocator(&limit);
+    inserter::Inserter::with_allocator(&mut other, &limit).insert_vec((0..500u64).collect()).expect("Failed to insert items");
+    assert_eq!(limit.available(), 12_000);
+
+    let mut iter = SortBuf::with_allocator(&limit).into_iter();
+    iter.push_bucket(Bucket::from_unsorted((0..500u64).collect())).expect("Failed to push bucket");
+    assert_eq!(limit.available(), 8_000);
+    inserter::Inserter::new(&mut iter).insert_vec((500..1000u64).collect()).expect("Failed to insert items");
+    assert_eq!(limit.available(), 4_000);
+    assert!(iter.push_bucket(Bucket::from_unsorted((0..1000u64).collect())).is_err());
+
+    assert!(iter.by_ref().eq((0..1000).rev()));
+    assert_eq!(limit.available(), 12_000);
+    drop(other);
+    assert_eq!(limit.available(), 16_000);
+}
+
+#[test]
+fn inserter_accounted() {
+    let items: Vec<_> = random_items(2_000).map(|i| (i, "x".repeat((i % 2048) as usize))).collect();
+    let max_item = items.iter().map(HeapSize::deep_size).max().unwrap_or_default();
+
+    let mut buf: SortBuf<_> = Default::default();
+    let mut sizes = Vec::new();
+    let inspect = adapters::Inspect::new(&mut buf, |b: &Bucket<_>| sizes.push(b.as_slice().deep_size()));
+    let mut inserter = inserter::Inserter::new(inspect);
+    inserter.set_bucket_bytesize(256 * 1024).set_heap_accounting(true);
+    let bytesize = inserter.bucket_bytesize();
+    let (chunked, single) = items.split_at(1_500);
+    chunked.chunks(300).for_each(|c| inserter.insert_items(c.iter().cloned()).expect("Failed to insert items"));
+    single.iter().cloned().for_each(|i| inserter.insert(i).expect("Failed to insert item"));
+    drop(inserter);
+
+    assert!(sizes.len() > 1);
+    assert!(sizes.iter().all(|s| *s < bytesize + max_item));
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 2_000);
+    assert_sorted(iter.map(Reverse));
+}
+
+#[test]
+fn inserter_accounted_limited() {
+    let item_size = core::mem::size_of::<String>();
+    let limit = MemoryLimit::new(64 * 1024);
+    let mut buf = SortBuf::with_allocator(&limit);
+    {
+        let mut inserter = inserter::Inserter::with_allocator(&mut buf, &limit);
+        inserter.set_bucket_bytesize(4096).set_heap_accounting(true);
+        inserter.insert_items((0..10).map(|i| format!("{i:0200}"))).expect("Failed to insert items");
+        // The inline storage claimed leaves room for the strings' contents
+        assert!(limit.limit() - limit.available() + 10 * 200 <= 4096);
+        assert!(limit.limit() - limit.available() >= 10 * item_size);
+    }
+    assert_eq!(buf.into_iter().len(), 10);
+    assert_eq!(limit.available(), limit.limit());
+}
+
+#[test]
+fn records() {
+    // Short records with common prefixes and zero bytes exercise the prefix comparison
+    let mut items: Vec<Vec<u8>> = random_items(10_500)
+        .map(|i| i.to_be_bytes().into_iter().chain(i.to_le_bytes()).take((i % 12) as usize).map(|b| b & 0x81).collect())
+        .collect();
+
+    let mut buf = RecordBuf::new();
+    items.chunks(1000).for_each(|chunk| {
+        let mut inserter = RecordInserter::new(&mut buf);
+        inserter.set_bucket_bytesize(4096);
+        inserter.insert_records(chunk).expect("Failed to insert records");
+    });
+    assert_eq!(buf.len(), 10_500);
+
+    items.sort_unstable();
+    assert!(buf.iter().eq(items.iter().map(Vec::as_slice)));
+    assert!(buf.iter_desc().eq(items.iter().rev().map(Vec::as_slice)));
+}
+
+#[test]
+fn records_bounded() {
+    struct Sizes(Vec<(usize, usize)>);
+
+    impl RecordAccumulator for Sizes {
+        fn add_record_bucket(&mut self, bucket: RecordBucket) -> error::InsertionResult<RecordBucket> {
+            self.0.push((bucket.len(), bucket.bytesize()));
+            Ok(())
+        }
+    }
+
+    let mut sizes = Sizes(Vec::new());
+    {
+        let mut inserter = RecordInserter::new(&mut sizes);
+        inserter.set_bucket_bytesize(4096);
+        for item in random_items(10_500) {
+            inserter.insert(&[item.to_be_bytes().as_slice(), b"xy"].concat()).expect("Failed to insert record");
+            assert!(inserter.bytesize() <= 4096);
+        }
+    }
+
+    // Full buckets have no room left for another record and no unused storage
+    let (last, full) = sizes.0.split_last().expect("No buckets committed");
+    assert!(full.iter().all(|(l, s)| *s <= 4096 && *s + *s / l > 4096));
+    assert!(full.iter().all(|(l, s)| s % l == 0));
+    assert_eq!(full.iter().chain([last]).map(|(l, _)| l).sum::<usize>(), 10_500);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn records_consumed() {
+    use std::sync::{Arc, RwLock};
+
+    let items: Vec<Vec<u8>> = random_items(10_500).map(|i| i.to_le_bytes()[..(i % 9) as usize].to_vec()).collect();
+    let (mut asc, mut desc) = (items[..5000].to_vec(), items[5000..].to_vec());
+
+    let bufs: [Arc<RwLock<RecordBuf>>; 2] = Default::default();
+    for (items, buf) in [&asc, &desc].into_iter().zip(&bufs) {
+        let mut inserter = RecordInserter::new(buf.clone());
+        inserter.set_bucket_bytesize(4096);
+        inserter.insert_records(items).expect("Failed to insert records");
+    }
+    let [asc_buf, desc_buf] = bufs.map(|b| Arc::into_inner(b).and_then(|b| b.into_inner().ok()).expect("Buffer still shared"));
+
+    asc.sort_unstable();
+    desc.sort_unstable();
+    let iter = asc_buf.into_iter();
+    assert_eq!(iter.len(), 5000);
+    assert!(iter.eq(asc));
+    assert!(desc_buf.into_iter_desc().eq(desc.into_iter().rev()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn records_lines() {
+    let mut buf = RecordBuf::new();
+    RecordInserter::new(&mut buf)
+        .insert_lines(b"pear\r\nfig\n\xffapple\n\nbanana".as_slice())
+        .expect("Failed to insert lines");
+    assert!(buf.iter().eq([b"".as_slice(), b"banana", b"fig", b"pear", b"\xffapple"]));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn fill_parallel() {
+    let buf = SortBuf::new().fill_parallel(
+        NonZeroUsize::new(4).expect("Failed to construct number of threads"),
+        |inserter, n| {
+            inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+            inserter.insert_items(random_items(10_500).skip(n).step_by(4))
+        },
+    ).map_err(|(e, _)| e).expect("Failed to insert items");
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_iter_parallel() {
+    // Big items make for multiple buckets of the default size
+    let buf = SortBuf::from_iter_parallel(
+        random_items(10_500).map(|i| (i, [0u8; 4096])),
+        NonZeroUsize::new(4).expect("Failed to construct number of workers"),
+    ).map_err(|(e, ..)| e).expect("Failed to insert items");
+    assert!(buf.buckets.len() > 1);
+
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_iter_parallel_small() {
+    let workers = NonZeroUsize::new(2).expect("Failed to construct number of workers");
+    let buf = SortBuf::from_iter_parallel(0u64..10, workers).map_err(|(e, ..)| e).expect("Failed to insert items");
+    let items = buf.into_sorted_vec().map_err(|(e, _)| e).expect("Failed to merge buckets");
+    assert_eq!(items.capacity(), 10);
+
+    let buf = SortBuf::from_iter_parallel(std::iter::repeat(()).take(10), workers)
+        .map_err(|(e, ..)| e)
+        .expect("Failed to insert items");
+    assert_eq!(buf.into_iter().len(), 10);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_iter_parallel_failing() {
+    let acc = std::sync::Mutex::new(adapters::FailAfter::new(SortBuf::new(), 1));
+    let workers = NonZeroUsize::new(2).expect("Failed to construct number of workers");
+    let mut items = random_items(50_000).map(|i| (i, [0u8; 4096]));
+    let (_, rejected) = parallel::fill_from_iter(&acc, items.by_ref(), workers)
+        .expect_err("Inserted despite injected failure");
+    assert!(!rejected.is_empty());
+
+    let buf = acc.into_inner().expect("Could not lock mutex!").into_inner();
+    let inserted = buf.into_iter().len();
+    let rejected = rejected.iter().map(Vec::len).sum::<usize>();
+    assert_eq!(inserted + rejected + items.count(), 50_000);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn collector() {
+    let collector = Collector::spawn(SortBuf::new(), 2);
+    std::thread::scope(|s| (0..4).for_each(|n| {
+        let mut inserter = collector.inserter();
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        s.spawn(move || inserter
+            .insert_items(random_items(10_500).skip(n).step_by(4))
+            .expect("Failed to insert items"));
+    }));
+
+    let iter = collector.finish().map_err(|(e, ..)| e).expect("Failed to collect buckets").into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn collector_failing() {
+    let collector = Collector::spawn(adapters::FailAfter::new(SortBuf::new(), 1), 0);
+    let mut inserter = collector.inserter();
+    inserter.insert_vec(random_items(1000).collect()).expect("Failed to insert items");
+    inserter.insert_vec(random_items(1500).collect()).expect("Failed to insert items");
+    let (_, items) = inserter.insert_vec(random_items(2000).collect()).expect_err("Inserted despite failure");
+    assert_eq!(items.len(), 2000);
+    drop(inserter);
+
+    let (_, acc, bucket) = collector.finish().expect_err("Collected despite injected failure");
+    assert_eq!(acc.into_inner().into_iter().len(), 1000);
+    assert_eq!(bucket.len(), 1500);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn collector_disconnected() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    drop(receiver);
+
+    let mut inserter = inserter::Inserter::new(sender);
+    let (_, items) = inserter.insert_vec(random_items(1500).collect()).expect_err("Inserted into disconnected channel");
+    assert_eq!(items.len(), 1500);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn chunks_ordered() {
+    let mut buf: SortBuf<_> = Default::default();
+    {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.extend(random_items(10_500));
+    }
+
+    let mut items = Vec::new();
+    buf.for_each_chunk_ordered(
+        NonZeroUsize::new(4).expect("Failed to construct number of threads"),
+        NonZeroUsize::new(100).expect("Failed to construct chunk size"),
+        |chunk| chunk.into_iter().map(Reverse).collect::<Vec<_>>(),
+        |chunk| items.extend(chunk),
+    );
+    assert_eq!(items.len(), 10_500);
+    assert_sorted(items.into_iter());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn chunks_ordered_panicking() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let buf: SortBuf<_> = random_items(10_500).collect();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let mut chunks = 0;
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| buf.for_each_chunk_ordered(
+            NonZeroUsize::new(4).expect("Failed to construct number of threads"),
+            NonZeroUsize::new(100).expect("Failed to construct chunk size"),
+            |_| assert_ne!(calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed), 3, "Injected panic"),
+            |_| chunks += 1,
+        )));
+        sender.send((res.is_err(), chunks)).expect("Failed to send result");
+    });
+
+    let (panicked, chunks) = receiver
+        .recv_timeout(std::time::Duration::from_secs(20))
+        .expect("Processing chunks did not finish");
+    assert!(panicked);
+    assert!(chunks < 105);
+}
+
+
+#[test]
+fn ext_sorted() {
+    let mut items: Vec<_> = random_items(10_500).collect();
+    let ascending = items.iter().copied().sortbuf_sorted();
+    assert_eq!(ascending.len(), 10_500);
+    assert_sorted(ascending);
+    assert_sorted(items.iter().copied().map(Reverse).sortbuf_sorted_desc().map(Reverse));
+
+    let sorted: Vec<_> = items.iter().copied().sortbuf_sorted_by_key(|i| i % 1000).collect();
+    items.sort_by_key(|i| i % 1000);
+    assert!(sorted.iter().map(|i| i % 1000).eq(items.iter().map(|i| i % 1000)));
+}
+
+#[test]
+fn sortbuf_collected() {
+    let mut buf: SortBuf<_> = random_items(5000).collect();
+    buf.extend(random_items(5500));
+    let iter = buf.into_iter();
+    assert_eq!(iter.len(), 10_500);
+    assert_sorted(iter.map(Reverse))
+}
+
+
+#[test]
+fn drain_watermarked() {
+    let mut buf: SortBuf<_> = Default::default();
+    let items: Vec<_> = random_items(10_500).enumerate().map(|(n, i)| (n as Item) << 40 | i >> 24).collect();
+    let mut drained = Vec::new();
+
+    // Items arrive at most `1 << 50` late relative to the running maximum
+    for chunk in items.chunks(1000) {
+        let mut inserter = inserter::Inserter::new(&mut buf);
+        inserter.set_bucket_size(NonZeroUsize::new(100).expect("Failed to construct bucket size"));
+        inserter.insert_items_reversed(chunk.iter().copied()).expect("Failed to insert items");
+        drop(inserter);
+
+        let max = chunk.iter().copied().max().unwrap_or_default();
+        let watermark = max.saturating_sub(1 << 50);
+        let len = drained.len();
+        drained.extend(buf.drain_below(&watermark).expect("Failed to drain items"));
+        assert!(drained[len..].iter().all(|i| *i < watermark));
+    }
+    drained.extend(buf.unreversed());
+
+    assert_eq!(drained.len(), 10_500);
+    assert_sorted(drained.into_iter());
+}
+
+#[test]
+fn drain_from() {
+    let mut buf: SortBuf<_> = Default::default();
+    inserter::Inserter::new(&mut buf).insert_items(random_items(10_500)).expect("Failed to insert items");
+
+    let bound = Item::MAX / 2;
+    let upper: Vec<_> = buf.drain_from(&bound).expect("Failed to drain items").collect();
+    assert!(upper.iter().all(|i| *i >= bound));
+    assert_sorted(upper.iter().map(Reverse));
+
+    let lower: Vec<_> = buf.into_iter().collect();
+    assert!(lower.iter().all(|i| *i < bound));
+    assert_eq!(upper.len() + lower.len(), 10_500);
+}
+
 
 #[test]
 fn bucket_sorted() {
-    let bucket = bucket::Bucket::new(random_items(1000).collect::<Vec<_>>());
+    let bucket = bucket::Bucket::from_unsorted(random_items(1000).collect::<Vec<_>>());
+    assert_sorted(bucket::SortedBucket::from(bucket).map(Reverse))
+}
+
+#[test]
+fn bucket_rejoined() {
+    let items: Vec<_> = random_items(1000).collect();
+    let mut bucket = bucket::SortedBucket::from(bucket::Bucket::from_unsorted(items.clone()));
+    let capacity = bucket.len() + bucket.overcapacity();
+
+    let upper = bucket.split_upper(|i| *i < Item::MAX / 2, &Global).expect("Failed to split bucket");
+    assert_eq!(upper.len(), items.iter().filter(|i| **i >= Item::MAX / 2).count());
+    bucket.rejoin(upper);
+    assert_eq!(bucket.len() + bucket.overcapacity(), capacity);
+    assert_sorted(bucket.map(Reverse));
+}
+
+#[test]
+fn bucket_presorted() {
+    let bucket = bucket::Bucket::from_unsorted((0..1000).rev().chain([0, 0]).collect::<Vec<Item>>());
+    assert_sorted(bucket::SortedBucket::from(bucket).map(Reverse));
+
+    let bucket = bucket::Bucket::from_unsorted((0..1000).chain([999, 0]).collect::<Vec<Item>>());
     assert_sorted(bucket::SortedBucket::from(bucket).map(Reverse))
 }
 
+#[test]
+fn bucket_api() {
+    let mut bucket = bucket::Bucket::from_unsorted(random_items(1000).collect());
+    assert_sorted(bucket.as_slice().iter());
+
+    let key = bucket.as_slice()[600];
+    let upper = bucket.split_off(&key);
+    assert_eq!(bucket.len() + upper.len(), 1000);
+    assert!(bucket.max() < Some(&key));
+    assert_eq!(upper.min(), Some(&key));
+
+    let items = upper.into_vec();
+    assert!(bucket::Bucket::from_sorted(items.clone()).is_ok());
+    assert!(bucket::Bucket::from_sorted(items.into_iter().rev().collect()).is_err());
+}
+
+
+#[test]
+fn map_combined() {
+    let mut map = SortMap::with_combiner(|a: usize, b| a + b);
+    {
+        let mut inserter = inserter::Inserter::new(&mut map);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_pairs(random_items(10_500).map(|i| (i % 100, 1))).expect("Failed to insert items");
+    }
+
+    let pairs: Vec<_> = map.into_iter().collect();
+    assert_eq!(pairs.len(), 100);
+    assert_sorted(pairs.iter().map(|(k, _)| Reverse(k)));
+    assert_eq!(pairs.iter().map(|(_, v)| v).sum::<usize>(), 10_500);
+}
+
+#[test]
+fn map_uncombined() {
+    let mut map = SortMap::new();
+    {
+        let mut inserter = inserter::Inserter::new(&mut map);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_pairs(random_items(10_500).map(|i| (i % 100, i))).expect("Failed to insert items");
+    }
+
+    let iter = map.into_iter();
+    assert_eq!(iter.size_hint(), (10_500, Some(10_500)));
+    assert_sorted(iter.map(|(k, _)| Reverse(k)));
+}
+
+#[test]
+fn map_combined_shrinking() {
+    let items: Vec<_> = random_items(1000).map(|i| KeyValue::new(i % 100, 1usize)).collect();
+    let bucket = bucket::Bucket::from_unsorted(items).combine_values(|a, b| a + b);
+    assert_eq!(bucket.len(), 100);
+    assert_sorted(bucket.as_slice().iter().map(|p| p.key));
+    assert_eq!(bucket.as_slice().iter().map(|p| p.value).sum::<usize>(), 1000);
+    assert_eq!(bucket.into_vec().capacity(), 100);
+}
+
+
+#[test]
+fn partitioned() {
+    let mut bufs: Vec<SortBuf<_>> = (0..4).map(|_| Default::default()).collect();
+    {
+        let sample_size = NonZeroUsize::new(100).expect("Failed to construct sample size");
+        let mut inserter = PartitionedInserter::new(bufs.iter_mut(), sample_size);
+        inserter.set_bucket_size(NonZeroUsize::new(1000).expect("Failed to construct bucket size"));
+        inserter.insert_items(random_items(10_500)).expect("Failed to insert items");
+        assert_eq!(inserter.splitters().map(<[_]>::len), Some(3));
+    }
+
+    let partitions: Vec<Vec<_>> = bufs.into_iter().map(|b| b.into_iter().collect()).collect();
+    assert_eq!(partitions.iter().map(Vec::len).sum::<usize>(), 10_500);
+    assert!(partitions.iter().all(|p| !p.is_empty()));
+    assert_sorted(partitions.iter().rev().flatten().map(Reverse));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn partitioned_shared() {
+    let bufs: Vec<std::sync::Mutex<SortBuf<_>>> = (0..4).map(|_| Default::default()).collect();
+    let sample_size = NonZeroUsize::new(100).expect("Failed to construct sample size");
+    let mut first = PartitionedInserter::new(bufs.iter(), sample_size);
+    first.insert_items(random_items(5_000)).expect("Failed to insert items");
+
+    let splitters = first.splitters().expect("No splitters chosen").to_vec();
+    std::thread::scope(|scope| {
+        for n in 1..4 {
+            let (bufs, splitters) = (&bufs, splitters.clone());
+            scope.spawn(move || {
+                let mut inserter = PartitionedInserter::with_splitters(bufs.iter(), splitters);
+                inserter.insert_items(random_items(5_000 * (n + 1)).skip(5_000 * n)).expect("Failed to insert items");
+            });
+        }
+    });
+    drop(first);
+
+    let partitions: Vec<Vec<_>> = bufs
+        .into_iter()
+        .map(|b| b.into_inner().expect("Could not lock mutex!").into_iter().collect())
+        .collect();
+    assert_eq!(partitions.iter().map(Vec::len).sum::<usize>(), 20_000);
+    assert_sorted(partitions.iter().rev().flatten().map(Reverse));
+}
+
+#[test]
+fn partitioned_recovering() {
+    struct Flaky<'a> {
+        buf: &'a mut SortBuf<Item>,
+        budget: &'a core::cell::Cell<usize>,
+    }
+
+    impl BucketAccumulator for Flaky<'_> {
+        type Item = Item;
+
+        fn add_bucket(&mut self, bucket: Bucket<Item>) -> error::InsertionResult<Bucket<Item>> {
+            match self.budget.get().checked_sub(1) {
+                Some(budget) => {
+                    self.budget.set(budget);
+                    self.buf.add_bucket(bucket)
+                },
+                None => Err((error::InsertionError::injected(), bucket)),
+            }
+        }
+
+        fn reserve_buckets(&mut self, additional: usize) -> Result<(), error::InsertionError> {
+            if self.budget.get() == 0 {
+                return Err(error::InsertionError::injected())
+            }
+            self.buf.reserve_buckets(additional)
+        }
+    }
+
+    let budget = core::cell::Cell::new(0);
+    let mut bufs: Vec<SortBuf<_>> = (0..4).map(|_| Default::default()).collect();
+    {
+        let sample_size = NonZeroUsize::new(100).expect("Failed to construct sample size");
+        let accs = bufs.iter_mut().map(|buf| Flaky{buf, budget: &budget});
+        let mut inserter = PartitionedInserter::new(accs, sample_size);
+        inserter.set_bucket_size(NonZeroUsize::new(10).expect("Failed to construct bucket size"));
+
+        let mut items = random_items(1000);
+        let mut errors = 0;
+        while inserter.insert_items(items.by_ref()).is_err() {
+            errors += 1;
+            budget.set(1);
+        }
+        assert!(errors > 10);
+        assert_eq!(items.next(), None);
+        budget.set(usize::MAX);
+    }
+
+    let partitions: Vec<Vec<_>> = bufs.into_iter().map(|b| b.into_iter().collect()).collect();
+    assert_eq!(partitions.iter().map(Vec::len).sum::<usize>(), 1000);
+    assert_sorted(partitions.iter().rev().flatten().map(Reverse));
+}
+
+#[test]
+fn partitioned_small() {
+    let mut bufs: Vec<SortBuf<_>> = (0..4).map(|_| Default::default()).collect();
+    {
+        let sample_size = NonZeroUsize::new(100).expect("Failed to construct sample size");
+        let mut inserter = PartitionedInserter::new(bufs.iter_mut(), sample_size);
+        inserter.insert_items(random_items(50)).expect("Failed to insert items");
+        assert_eq!(inserter.splitters(), None);
+    }
+
+    let items = bufs.into_iter().rev().flatten();
+    assert_sorted(items.map(Reverse));
+}
+
 
 /// Construct an [Iterator] yielding `num` random items
 fn random_items(num: usize) -> impl Iterator<Item = Item> {